@@ -2,6 +2,7 @@ use crate::state::Config;
 use cosmwasm_std::Deps;
 use cosmwasm_std::DepsMut;
 use cosmwasm_std::StdResult;
+use cosmwasm_std::Uint128;
 use schemars::JsonSchema;
 use serde::{ Deserialize, Serialize };
 
@@ -24,7 +25,7 @@ impl OraiPriceOracle {
                 offer_amount: 1000000,
                 operations: vec![Operation {
                     orai_swap: OraiSwap {
-                        offer_asset_info: offer_asset_info,
+                        offer_asset_info,
                         ask_asset_info: AskAssetInfo {
                             token: UsdtContractAddr {
                                 contract_addr: usdt_contract_address,
@@ -54,7 +55,7 @@ impl OraiPriceOracle {
                 offer_amount: 1000000,
                 operations: vec![Operation {
                     orai_swap: OraiSwap {
-                        offer_asset_info: offer_asset_info,
+                        offer_asset_info,
                         ask_asset_info: AskAssetInfo {
                             token: UsdtContractAddr {
                                 contract_addr: usdt_contract_address,
@@ -112,7 +113,7 @@ pub struct OfferAssetInfo {
 impl OfferAssetInfo {
     pub fn new(native_token: NativeToken) -> Self {
         OfferAssetInfo {
-            native_token: native_token,
+            native_token,
         }
     }
 }
@@ -174,3 +175,33 @@ pub struct Amount {
 pub struct ExchangeRateResponse {
     pub amount: u128,
 }
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AssetInfo {
+    Token {
+        contract_addr: String,
+    },
+    NativeToken {
+        denom: String,
+    },
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SwapOperation {
+    OraiSwap {
+        offer_asset_info: AssetInfo,
+        ask_asset_info: AssetInfo,
+    },
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RouterExecuteMsg {
+    ExecuteSwapOperations {
+        operations: Vec<SwapOperation>,
+        minimum_receive: Option<Uint128>,
+        to: Option<String>,
+    },
+}
@@ -3,44 +3,126 @@ use cosmwasm_std::entry_point;
 use cosmwasm_std::{
     coin,
     coins,
+    from_json,
     to_json_binary,
     BankMsg,
     Binary,
     Coin,
     CosmosMsg,
+    Decimal,
     Deps,
     DepsMut,
     Env,
+    Event,
     MessageInfo,
+    Reply,
     Response,
     StdResult,
     SubMsg,
+    SubMsgResult,
     Uint128,
     StakingQuery,
+    Storage,
+    Order,
     AllDelegationsResponse,
+    WasmMsg,
 };
 
 use cosmwasm_std::DistributionMsg;
 use cosmwasm_std::StakingMsg;
+use cw2::set_contract_version;
+use cw20::Cw20ReceiveMsg;
+use cw_storage_plus::{ Bound, PrefixBound };
+use std::collections::BTreeMap;
 
-use crate::band::OraiPriceOracle;
+use crate::band::{ AssetInfo, OraiPriceOracle, RouterExecuteMsg, SwapOperation };
 use crate::error::ContractError;
 use crate::msg::{
-    ContractStatus,
+    ConfigChange,
+    Cw20HookMsg,
+    DepositToken,
     ExecuteMsg,
     ExecuteResponse,
     InstantiateMsg,
+    MigrateMsg,
+    OraiswapContract,
+    SudoMsg,
+    PauseFlags,
     QueryMsg,
     QueryResponse,
     ResponseStatus,
     SerializedWithdrawals,
+    RewardShare,
+    Role,
+    RoleMembers,
+    UnbondingBucket,
+    ValidatorDelegation,
+    ValidatorSolvency,
+    ValidatorWithWeight,
+    WithdrawalStatus,
+};
+use crate::state::{
+    self,
+    Config,
+    Epoch,
+    PendingAdmin,
+    PendingChange,
+    PendingRewardSwap,
+    PendingRewards,
+    PendingSwapDeposit,
+    StakingAction,
+    StakingOp,
+    Undelegation,
+    UserWithdrawal,
+    BUFFER_REFILLS,
+    CONFIG_ITEM,
+    LEGACY_CONFIG_STATUS,
+    CURRENT_EPOCH,
+    EPOCHS,
+    LIQUIDITY_BUFFER,
+    PENDING_DELEGATION,
+    FAILED_REDELEGATIONS,
+    FAILED_UNDELEGATIONS,
+    UNBONDING_REDELEGATIONS,
+    SHUTDOWN_EPOCH,
+    AUTO_WITHDRAWN_REWARDS,
+    REWARDS_TO_CONTRACT,
+    STAKE_POOL,
+    STAKING_OP_ID,
+    STAKING_OPS,
+    VALIDATOR_DELEGATIONS,
+    PENDING_ADMIN,
+    PENDING_CHANGES,
+    CHANGE_ID,
+    ROLE_MEMBERS,
+    PENDING_REWARD_SWAP,
+    PENDING_REWARDS,
+    PENDING_SWAP_DEPOSIT,
+    LEGACY_WITHDRAWALS_LIST,
+    USER_INFOS,
 };
-use crate::state::{ self, Config, UserWithdrawal, CONFIG_ITEM, USER_INFOS, WITHDRAWALS_LIST };
 use crate::utils;
 use cosmwasm_std::StdError;
 
 pub const UNBOUND_TIME: u64 = 21 * 24 * 60 * 60;
 pub const ORAI: &str = "orai";
+pub const MAX_BPS: u64 = 10_000;
+pub const EPOCH_PERIOD: u64 = 4 * 24 * 60 * 60;
+pub const ADMIN_PROPOSAL_EXPIRY: u64 = 7 * 24 * 60 * 60;
+pub const TIMELOCK_DELAY: u64 = 2 * 24 * 60 * 60;
+/// Unbonding entries the chain keeps per delegator and validator pair
+pub const MAX_UNBONDING_ENTRIES: u64 = 7;
+
+const CONTRACT_NAME: &str = env!("CARGO_PKG_NAME");
+const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+pub const SWAP_DEPOSIT_REPLY_ID: u64 = 1;
+pub const COMPOUND_REPLY_ID: u64 = 2;
+pub const WITHDRAW_REWARDS_REPLY_ID: u64 = 3;
+pub const SWAP_REWARDS_REPLY_ID: u64 = 4;
+pub const STAKING_REWARDS_REPLY_ID: u64 = 5;
+// Staking messages reply with this plus their operation id
+pub const STAKING_REPLY_ID_START: u64 = 1_000;
 
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn instantiate(
@@ -49,40 +131,154 @@ pub fn instantiate(
     _info: MessageInfo,
     msg: InstantiateMsg
 ) -> Result<Response, ContractError> {
-    let deposits = msg.deposits
-        .iter()
-        .map(|v| v.u128())
-        .collect::<Vec<_>>();
-
-    if deposits.is_empty() {
-        return Err(ContractError::Std(StdError::generic_err("Deposits array is empty")));
-    }
+    let deposits = validate_deposits(&msg.deposits)?;
 
-    let is_sorted = deposits
-        .as_slice()
-        .windows(2)
-        .all(|v| v[0] > v[1]);
-    if !is_sorted {
-        return Err(
-            ContractError::Std(StdError::generic_err("Specify deposits in decreasing order"))
-        );
-    }
+    validate_validators(deps.as_ref(), &msg.validators)?;
+    let reward_distribution = msg.reward_distribution.unwrap_or_default();
+    validate_reward_distribution(deps.as_ref(), &reward_distribution)?;
+    let unbonding_period = msg.unbonding_period.unwrap_or(UNBOUND_TIME);
+    let epoch_period = msg.epoch_period.unwrap_or(EPOCH_PERIOD);
+    validate_epoch_period(epoch_period, unbonding_period)?;
 
-    let admin = msg.admin.unwrap_or("".to_string());
+    let admin = msg.admin
+        .map(|admin| deps.api.addr_validate(&admin))
+        .transpose()?
+        .map(|admin| admin.to_string())
+        .unwrap_or_default();
     let initial_config: Config = Config {
-        status: ContractStatus::Active as u8,
-        admin: admin,
+        pause: PauseFlags::default(),
+        shutdown: false,
+        admin,
         validators: msg.validators,
         usd_deposits: deposits,
         oraiswap_contract: msg.oraiswap_contract,
+        swap_denoms: msg.swap_denoms.unwrap_or_default(),
+        unbonding_period,
+        buffer_bps: 0,
+        instant_withdraw_fee_bps: 0,
+        penalty_recipient: None,
+        epoch_period,
+        inactive_validators: vec![],
+        auto_compound: false,
+        reward_distribution,
+        timelock_delay: msg.timelock_delay.unwrap_or(TIMELOCK_DELAY),
     };
 
     CONFIG_ITEM.save(deps.storage, &initial_config)?;
+    // The SDK pays rewards to the delegator until told otherwise
+    REWARDS_TO_CONTRACT.save(deps.storage, &true)?;
     // initial_config.save(&deps.storage)?;
+    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
 
     Ok(Response::new())
 }
 
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn migrate(deps: DepsMut, env: Env, _msg: MigrateMsg) -> Result<Response, ContractError> {
+    // Move withdrawals from the per-user list into the indexed map
+    let legacy_withdrawals = LEGACY_WITHDRAWALS_LIST.range(
+        deps.storage,
+        None,
+        None,
+        Order::Ascending
+    ).collect::<StdResult<Vec<_>>>()?;
+
+    // Read before the config is saved again, which drops the legacy status
+    let legacy_status = LEGACY_CONFIG_STATUS.load(deps.storage)?.status;
+    let mut config = CONFIG_ITEM.load(deps.storage)?;
+    let mut config_changed = false;
+
+    // Weights used to be percentages, move them to basis points
+    let total_weight: u128 = config.validators
+        .iter()
+        .map(|v| v.weight)
+        .sum();
+    if total_weight == 100 {
+        for validator in config.validators.iter_mut() {
+            validator.weight = validator.weight.checked_mul(100).unwrap();
+        }
+        config_changed = true;
+    }
+
+    // A stopped contract stays fully paused under the new flags
+    if legacy_status.is_some() {
+        if legacy_status != Some(0) {
+            config.pause = PauseFlags::all();
+        }
+        config_changed = true;
+    }
+
+    if config_changed {
+        config.save(deps.storage)?;
+    }
+
+    // Start tracking per-validator stake from what is actually delegated
+    let tracked = VALIDATOR_DELEGATIONS.keys(deps.storage, None, None, Order::Ascending).next();
+    if tracked.is_none() {
+        for validator in config.validators.iter() {
+            let delegation = deps.querier.query_delegation(
+                &env.contract.address,
+                &validator.address
+            )?;
+            if let Some(delegation) = delegation {
+                VALIDATOR_DELEGATIONS.save(
+                    deps.storage,
+                    validator.address.clone(),
+                    &delegation.amount.amount.u128()
+                )?;
+            }
+        }
+    }
+
+    // Existing deposits start out as shares at a 1:1 rate
+    if STAKE_POOL.may_load(deps.storage)?.is_none() {
+        let mut pool = state::StakePool::default();
+        let user_infos = USER_INFOS.range(
+            deps.storage,
+            None,
+            None,
+            Order::Ascending
+        ).collect::<StdResult<Vec<_>>>()?;
+        for (user, mut user_info) in user_infos {
+            let staked = user_info.orai_deposit.saturating_sub(user_info.orai_buffered);
+            user_info.shares = pool.stake(staked);
+            USER_INFOS.save(deps.storage, user, &user_info)?;
+        }
+        STAKE_POOL.save(deps.storage, &pool)?;
+    }
+
+    // Reward withdrawals used to point the SDK at their recipient, point it
+    // back at the contract so rewards paid by staking messages are tracked
+    let mut messages: Vec<CosmosMsg> = vec![];
+    if REWARDS_TO_CONTRACT.may_load(deps.storage)?.is_none() {
+        messages.push(
+            CosmosMsg::Distribution(DistributionMsg::SetWithdrawAddress {
+                address: env.contract.address.to_string(),
+            })
+        );
+        REWARDS_TO_CONTRACT.save(deps.storage, &true)?;
+    }
+
+    let mut migrated = 0u64;
+    for (user, user_withdrawals) in legacy_withdrawals {
+        for withdrawal in user_withdrawals {
+            let id = state::next_withdrawal_id(deps.storage)?;
+            state::withdrawals().save(deps.storage, (user.clone(), id), &withdrawal)?;
+            migrated += 1;
+        }
+        LEGACY_WITHDRAWALS_LIST.remove(deps.storage, user);
+    }
+
+    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+
+    Ok(
+        Response::new()
+            .add_messages(messages)
+            .add_attribute("action", "migrate")
+            .add_attribute("migrated_withdrawals", migrated.to_string())
+    )
+}
+
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn execute(
     deps: DepsMut,
@@ -90,412 +286,716 @@ pub fn execute(
     info: MessageInfo,
     msg: ExecuteMsg
 ) -> Result<Response, ContractError> {
-    let response = match msg {
-        ExecuteMsg::ChangeAdmin { admin, .. } => try_change_admin(deps, env, info, admin),
-        ExecuteMsg::ChangeStatus { status, .. } => try_change_status(deps, env, info, status),
-        ExecuteMsg::Deposit { .. } => try_deposit(deps, env, info),
+    match msg {
+        ExecuteMsg::ProposeAdmin { admin, expires_in, .. } => {
+            try_propose_admin(deps, env, info, admin, expires_in)
+        }
+        ExecuteMsg::AcceptAdmin { .. } => try_accept_admin(deps, env, info),
+        ExecuteMsg::CancelAdminProposal { .. } => try_cancel_admin_proposal(deps, env, info),
+        ExecuteMsg::RenounceAdmin { .. } => try_renounce_admin(deps, env, info),
+        ExecuteMsg::GrantRole { role, address, .. } => {
+            try_grant_role(deps, env, info, role, address)
+        }
+        ExecuteMsg::RevokeRole { role, address, .. } => {
+            try_revoke_role(deps, env, info, role, address)
+        }
+        ExecuteMsg::Pause { flags, .. } => try_pause(deps, env, info, flags),
+        ExecuteMsg::ProposeChange { change, .. } => try_propose_change(deps, env, info, change),
+        ExecuteMsg::ExecuteChange { id, .. } => try_execute_change(deps, env, id),
+        ExecuteMsg::CancelChange { id, .. } => try_cancel_change(deps, env, info, id),
+        ExecuteMsg::SyncUnbondingPeriod { .. } => try_sync_unbonding_period(deps, env, info),
+        ExecuteMsg::Deposit { minimum_receive, .. } => {
+            try_deposit(deps, env, info, minimum_receive)
+        }
         ExecuteMsg::Withdraw { .. } => try_withdraw(deps, env, info),
-        ExecuteMsg::Claim { recipient, start, limit, .. } =>
-            try_claim(deps, env, info, recipient, start, limit),
-        ExecuteMsg::WithdrawRewards { recipient, .. } => {
-            try_withdraw_rewards(deps, env, info, recipient)
+        ExecuteMsg::InstantWithdraw { .. } => try_instant_withdraw(deps, env, info),
+        ExecuteMsg::CancelWithdrawal { id, .. } => try_cancel_withdrawal(deps, env, info, id),
+        ExecuteMsg::Claim { recipient, limit, .. } => try_claim(deps, env, info, recipient, limit),
+        ExecuteMsg::ClaimFor { addresses, limit, .. } => try_claim_for(deps, env, addresses, limit),
+        ExecuteMsg::WithdrawRewards { recipient, swap_minimum_price, .. } => {
+            try_withdraw_rewards(deps, env, info, recipient, swap_minimum_price)
+        }
+        ExecuteMsg::ProcessEpoch { .. } => try_process_epoch(deps, env),
+        ExecuteMsg::EmergencyShutdown { .. } => try_emergency_shutdown(deps, env, info),
+        ExecuteMsg::ProcessShutdown { limit, .. } => try_process_shutdown(deps, env, limit),
+        ExecuteMsg::CheckValidators { .. } => try_check_validators(deps, env),
+        ExecuteMsg::RetryStaking { .. } => try_retry_staking(deps, env),
+        ExecuteMsg::Compound { .. } => try_compound(deps, env),
+        ExecuteMsg::Receive(cw20_msg) => try_receive(deps, env, info, cw20_msg),
+    }
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn sudo(deps: DepsMut, _env: Env, msg: SudoMsg) -> Result<Response, ContractError> {
+    let mut config = CONFIG_ITEM.load(deps.storage)?;
+
+    let action = match msg {
+        SudoMsg::SetAdmin { admin } => {
+            config.admin = deps.api.addr_validate(&admin)?.to_string();
+            // A handover started by the replaced admin must not go through
+            PENDING_ADMIN.remove(deps.storage);
+            "sudo set admin"
+        }
+        SudoMsg::SetPauseFlags { flags } => {
+            config.pause = flags;
+            "sudo set pause flags"
+        }
+        SudoMsg::UpdateOracle { oraiswap_contract } => {
+            validate_oracle(deps.as_ref(), &oraiswap_contract)?;
+            config.oraiswap_contract = oraiswap_contract;
+            "sudo updated oracle"
         }
     };
 
-    return response;
+    CONFIG_ITEM.save(deps.storage, &config)?;
+
+    Ok(Response::new().add_attribute("action", action))
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn reply(deps: DepsMut, env: Env, msg: Reply) -> Result<Response, ContractError> {
+    match msg.id {
+        SWAP_DEPOSIT_REPLY_ID => reply_swap_deposit(deps, env),
+        COMPOUND_REPLY_ID => reply_compound(deps, env),
+        WITHDRAW_REWARDS_REPLY_ID => reply_withdraw_rewards(deps, env),
+        SWAP_REWARDS_REPLY_ID => reply_swap_rewards(deps, env),
+        STAKING_REWARDS_REPLY_ID => reply_staking_rewards(deps, env, msg),
+        id if id >= STAKING_REPLY_ID_START => reply_staking(deps, env, msg),
+        id => Err(ContractError::Std(StdError::generic_err(format!("Unknown reply id: {}", id)))),
+    }
 }
 
 #[cfg_attr(not(feature = "library"), entry_point)]
-pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
+pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
     match msg {
         QueryMsg::Config {} => to_json_binary(&query_config(deps)?),
         QueryMsg::UserInfo { address } => to_json_binary(&query_user_info(deps, address)?),
-        QueryMsg::Withdrawals { address, start, limit } =>
-            to_json_binary(&query_withdrawals(deps, address, start, limit)?),
+        QueryMsg::Withdrawals { address, status, start_after, limit } =>
+            to_json_binary(&query_withdrawals(deps, env, address, status, start_after, limit)?),
+        QueryMsg::UnbondingSchedule { from, to, bucket_seconds } =>
+            to_json_binary(&query_unbonding_schedule(deps, from, to, bucket_seconds)?),
+        QueryMsg::CurrentEpoch {} => to_json_binary(&query_current_epoch(deps, env)?),
+        QueryMsg::Solvency {} => to_json_binary(&query_solvency(deps, env)?),
+        QueryMsg::Delegations {} => to_json_binary(&query_delegations(deps, env)?),
+        QueryMsg::PendingAdmin {} => to_json_binary(&query_pending_admin(deps, env)?),
+        QueryMsg::Roles {} => to_json_binary(&query_roles(deps)?),
+        QueryMsg::PendingChanges {} => to_json_binary(&query_pending_changes(deps)?),
     }
 }
 
-pub fn try_change_admin(
+pub fn try_propose_admin(
     deps: DepsMut,
-    _env: Env,
+    env: Env,
     info: MessageInfo,
-    new_admin: String
+    new_admin: String,
+    expires_in: Option<u64>
 ) -> Result<Response, ContractError> {
     let config: Config = CONFIG_ITEM.load(deps.storage)?;
     if info.sender.clone() != config.admin {
         return Err(ContractError::Std(StdError::generic_err("Unauthorized")));
     }
 
+    let new_admin = deps.api.addr_validate(&new_admin)?.to_string();
+    let expires_at = env.block.time
+        .seconds()
+        .checked_add(expires_in.unwrap_or(ADMIN_PROPOSAL_EXPIRY))
+        .unwrap();
+
+    PENDING_ADMIN.save(
+        deps.storage,
+        &(PendingAdmin {
+            address: new_admin.clone(),
+            expires_at,
+        })
+    )?;
+
+    Ok(
+        Response::new()
+            .add_attribute("action", "proposed admin")
+            .add_attribute("admin", new_admin)
+            .add_attribute("expires_at", expires_at.to_string())
+    )
+}
+
+pub fn try_accept_admin(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo
+) -> Result<Response, ContractError> {
+    let pending = PENDING_ADMIN.may_load(deps.storage)?.ok_or(ContractError::NoAdminProposal {})?;
+    if info.sender != pending.address {
+        return Err(ContractError::Std(StdError::generic_err("Unauthorized")));
+    }
+
+    if env.block.time.seconds() >= pending.expires_at {
+        return Err(ContractError::AdminProposalExpired {
+            expires_at: pending.expires_at,
+        });
+    }
+
+    PENDING_ADMIN.remove(deps.storage);
     CONFIG_ITEM.update(
         deps.storage,
         |mut exists| -> StdResult<_> {
-            exists.admin = new_admin;
+            exists.admin = pending.address.clone();
             Ok(exists)
         }
     )?;
 
-    Ok(Response::new().add_attribute("action", "changed admin"))
+    let answer = to_json_binary(
+        &(ExecuteResponse::ChangeAdmin {
+            status: ResponseStatus::Success,
+        })
+    )?;
+
+    Ok(
+        Response::new()
+            .add_attribute("action", "changed admin")
+            .add_attribute("admin", pending.address)
+            .set_data(answer)
+    )
 }
 
-pub fn try_change_status(
+pub fn try_cancel_admin_proposal(
     deps: DepsMut,
     _env: Env,
-    info: MessageInfo,
-    status: ContractStatus
+    info: MessageInfo
+) -> Result<Response, ContractError> {
+    let config: Config = CONFIG_ITEM.load(deps.storage)?;
+    if info.sender.clone() != config.admin {
+        return Err(ContractError::Std(StdError::generic_err("Unauthorized")));
+    }
+
+    if PENDING_ADMIN.may_load(deps.storage)?.is_none() {
+        return Err(ContractError::NoAdminProposal {});
+    }
+    PENDING_ADMIN.remove(deps.storage);
+
+    Ok(Response::new().add_attribute("action", "cancelled admin proposal"))
+}
+
+/// Leaves the contract without an admin, which cannot be undone
+pub fn try_renounce_admin(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo
 ) -> Result<Response, ContractError> {
     let config: Config = CONFIG_ITEM.load(deps.storage)?;
     if info.sender.clone() != config.admin {
         return Err(ContractError::Std(StdError::generic_err("Unauthorized")));
     }
 
+    PENDING_ADMIN.remove(deps.storage);
     CONFIG_ITEM.update(
         deps.storage,
         |mut exists| -> StdResult<_> {
-            exists.status = status as u8;
+            exists.admin = String::new();
             Ok(exists)
         }
     )?;
-    Ok(Response::new().add_attribute("action", "changed status"))
-}
 
-pub fn get_received_funds(_deps: &DepsMut, info: &MessageInfo) -> Result<Coin, ContractError> {
-    match info.funds.get(0) {
-        None => {
-            return Err(ContractError::Std(StdError::generic_err("No Funds")));
-        }
-        Some(received) => {
-            /* Amount of tokens received cannot be zero */
-            if received.amount.is_zero() {
-                return Err(ContractError::Std(StdError::generic_err("Not Allow Zero Amount")));
-            }
+    Ok(Response::new().add_attribute("action", "renounced admin"))
+}
 
-            /* Allow to receive only token denomination defined
-            on contract instantiation "config.stable_denom" */
-            if received.denom.clone() != "orai" {
-                return Err(ContractError::Std(StdError::generic_err("Unsopported token")));
-            }
+pub fn try_grant_role(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    role: Role,
+    address: String
+) -> Result<Response, ContractError> {
+    let config: Config = CONFIG_ITEM.load(deps.storage)?;
+    config.assert_role(deps.storage, info.sender.as_str(), Role::Owner)?;
 
-            /* Only one token can be received */
-            if info.funds.len() > 1 {
-                return Err(ContractError::Std(StdError::generic_err("Not Allowed Multiple Funds")));
-            }
-            Ok(received.clone())
-        }
+    if role == Role::Owner {
+        return Err(
+            ContractError::Std(StdError::generic_err("The owner changes through ProposeAdmin"))
+        );
     }
-}
 
-pub fn try_deposit(deps: DepsMut, env: Env, info: MessageInfo) -> Result<Response, ContractError> {
-    let config = CONFIG_ITEM.load(deps.storage)?;
-    config.assert_contract_active()?;
+    let address = deps.api.addr_validate(&address)?.to_string();
+    ROLE_MEMBERS.save(deps.storage, (role.key().to_string(), address.clone()), &true)?;
 
-    let sender = info.sender.to_string();
+    Ok(
+        Response::new()
+            .add_attribute("action", "granted role")
+            .add_attribute("role", role.key())
+            .add_attribute("address", address)
+    )
+}
 
-    let staked_amount = get_staked_amount(deps.as_ref(), &sender);
+pub fn try_revoke_role(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    role: Role,
+    address: String
+) -> Result<Response, ContractError> {
+    let config: Config = CONFIG_ITEM.load(deps.storage)?;
+    config.assert_role(deps.storage, info.sender.as_str(), Role::Owner)?;
 
-    let received_funds = get_received_funds(&deps, &info)?;
+    let key = (role.key().to_string(), address.clone());
+    if !ROLE_MEMBERS.has(deps.storage, key.clone()) {
+        return Err(ContractError::Std(StdError::generic_err("Address does not have the role")));
+    }
+    ROLE_MEMBERS.remove(deps.storage, key);
 
-    let mut orai_deposit = received_funds.amount.u128();
+    Ok(
+        Response::new()
+            .add_attribute("action", "revoked role")
+            .add_attribute("role", role.key())
+            .add_attribute("address", address)
+    )
+}
 
-    // Get Tier from staking amount
+/// Pauses the operations set in `flags`, leaving the others as they are
+pub fn try_pause(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    flags: PauseFlags
+) -> Result<Response, ContractError> {
+    let config: Config = CONFIG_ITEM.load(deps.storage)?;
+    config.assert_role(deps.storage, info.sender.as_str(), Role::Pauser)?;
 
-    let old_user_info = USER_INFOS.may_load(deps.storage, sender.clone())?.unwrap_or(
-        state::UserInfo {
-            ..Default::default()
+    CONFIG_ITEM.update(
+        deps.storage,
+        |mut exists| -> StdResult<_> {
+            exists.pause = exists.pause.union(&flags);
+            Ok(exists)
         }
-    );
-
-    let total_usd_deposit = old_user_info.usd_deposit
-        .checked_add(staked_amount.staked_usd_amount)
-        .unwrap();
-    let tier = config.tier_by_deposit(total_usd_deposit);
-
-    let mut user_info = USER_INFOS.may_load(deps.storage, sender)?.unwrap_or(state::UserInfo {
-        tier,
-        ..Default::default()
-    });
-    //
+    )?;
+    Ok(Response::new().add_attribute("action", "paused"))
+}
 
-    // Add already staked orai and last user's orai deposit
-    orai_deposit = orai_deposit.checked_add(staked_amount.staked_orai_amount).unwrap();
+pub fn try_sync_unbonding_period(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo
+) -> Result<Response, ContractError> {
+    let config: Config = CONFIG_ITEM.load(deps.storage)?;
+    config.assert_role(deps.storage, info.sender.as_str(), Role::Keeper)?;
 
-    let orai_price_ocracle = OraiPriceOracle::new(&deps)?;
+    // Pending withdrawals keep the period they were created with
+    let unbonding_period = utils::query_unbonding_period(deps.as_ref())?;
+    // A longer period needs the epoch period raised first
+    validate_epoch_period(config.epoch_period, unbonding_period)?;
 
-    let usd_deposit: u128 = orai_price_ocracle.usd_amount(orai_deposit);
+    CONFIG_ITEM.update(
+        deps.storage,
+        |mut exists| -> StdResult<_> {
+            exists.unbonding_period = unbonding_period;
+            Ok(exists)
+        }
+    )?;
+    Ok(
+        Response::new()
+            .add_attribute("action", "synced unbonding period")
+            .add_attribute("unbonding_period", unbonding_period.to_string())
+    )
+}
 
-    let current_tier = user_info.tier;
-    let old_usd_deposit = user_info.usd_deposit;
-    let new_usd_deposit = old_usd_deposit.checked_add(usd_deposit).unwrap();
+/// Queues `change`, which can be executed once the timelock delay has passed
+pub fn try_propose_change(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    change: ConfigChange
+) -> Result<Response, ContractError> {
+    let config: Config = CONFIG_ITEM.load(deps.storage)?;
+    config.assert_role(deps.storage, info.sender.as_str(), change.role())?;
 
-    let new_tier = config.tier_by_deposit(new_usd_deposit);
+    validate_change(deps.as_ref(), &change)?;
 
-    if current_tier == new_tier {
-        if current_tier == config.max_tier() {
-            return Err(ContractError::Std(StdError::generic_err("Reached max tier")));
-        }
+    let id = CHANGE_ID.may_load(deps.storage)?.unwrap_or_default();
+    CHANGE_ID.save(deps.storage, &id.checked_add(1).unwrap())?;
 
-        let next_tier = current_tier.checked_sub(1).unwrap();
-        let next_tier_deposit: u128 = config.deposit_by_tier(next_tier);
+    let executable_at = env.block.time.seconds().checked_add(config.timelock_delay).unwrap();
+    PENDING_CHANGES.save(
+        deps.storage,
+        id,
+        &(PendingChange {
+            change,
+            proposer: info.sender.to_string(),
+            executable_at,
+        })
+    )?;
 
-        let expected_deposit_usd = next_tier_deposit
-            .checked_sub(old_usd_deposit + staked_amount.staked_usd_amount)
-            .unwrap();
-        let expected_deposit_orai = orai_price_ocracle.orai_amount(expected_deposit_usd);
+    Ok(
+        Response::new()
+            .add_attribute("action", "proposed change")
+            .add_attribute("id", id.to_string())
+            .add_attribute("executable_at", executable_at.to_string())
+    )
+}
 
-        let err_msg = format!(
-            "You should deposit at least {} USD ({} orai) for {}",
-            expected_deposit_usd,
-            expected_deposit_orai,
-            orai_deposit
-        );
+/// Applies a queued change once its delay has passed, callable by anyone
+pub fn try_execute_change(deps: DepsMut, env: Env, id: u64) -> Result<Response, ContractError> {
+    let pending = PENDING_CHANGES.may_load(deps.storage, id)?.ok_or_else(||
+        ContractError::Std(StdError::generic_err("Change not found"))
+    )?;
 
+    if env.block.time.seconds() < pending.executable_at {
+        let err_msg = format!("Change {} can be executed after {}", id, pending.executable_at);
         return Err(ContractError::Std(StdError::generic_err(&err_msg)));
     }
 
-    let mut messages: Vec<SubMsg> = Vec::with_capacity(2);
-    let new_tier_deposit = config.deposit_by_tier(new_tier);
+    // Validators may have left the active set while the change was queued
+    validate_change(deps.as_ref(), &pending.change)?;
 
-    let orai_refund = orai_deposit
-        .checked_sub(orai_price_ocracle.orai_amount(new_tier_deposit))
-        .unwrap();
+    PENDING_CHANGES.remove(deps.storage, id);
+    let response = apply_change(deps, pending.change)?;
 
-    if orai_refund != 0 {
-        let send_msg = BankMsg::Send {
-            to_address: info.sender.to_string(),
-            amount: coins(orai_refund, ORAI),
-        };
+    Ok(response.add_attribute("id", id.to_string()))
+}
 
-        let msg = CosmosMsg::Bank(send_msg);
+pub fn try_cancel_change(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    id: u64
+) -> Result<Response, ContractError> {
+    let pending = PENDING_CHANGES.may_load(deps.storage, id)?.ok_or_else(||
+        ContractError::Std(StdError::generic_err("Change not found"))
+    )?;
 
-        messages.push(SubMsg::new(msg));
-    }
-    let old_orai_deposit = user_info.orai_deposit;
-    user_info.tier = new_tier;
-    user_info.timestamp = env.block.time.seconds();
-    user_info.orai_deposit = orai_price_ocracle
-        .orai_amount(new_tier_deposit)
-        .checked_sub(staked_amount.staked_orai_amount)
-        .unwrap();
-    user_info.usd_deposit = orai_price_ocracle.usd_amount(user_info.orai_deposit) + 1;
-    USER_INFOS.save(deps.storage, info.sender.to_string(), &user_info)?;
+    let config: Config = CONFIG_ITEM.load(deps.storage)?;
+    config.assert_role(deps.storage, info.sender.as_str(), pending.change.role())?;
 
-    let validators = config.validators;
+    PENDING_CHANGES.remove(deps.storage, id);
 
-    for validator in validators {
-        let individual_amount =
-            ((user_info.orai_deposit - old_orai_deposit) * validator.clone().weight) / 100;
-        let delegate_msg = StakingMsg::Delegate {
-            validator: validator.address,
-            amount: coin(individual_amount, ORAI),
-        };
+    Ok(
+        Response::new()
+            .add_attribute("action", "cancelled change")
+            .add_attribute("id", id.to_string())
+    )
+}
 
-        let msg: CosmosMsg = CosmosMsg::Staking(delegate_msg);
+pub fn validate_change(deps: Deps, change: &ConfigChange) -> Result<(), ContractError> {
+    match change {
+        ConfigChange::Deposits { deposits } => {
+            validate_deposits(deposits)?;
+        }
+        ConfigChange::Validators { validators } => {
+            validate_validators(deps, validators)?;
+        }
+        ConfigChange::Oracle { oraiswap_contract } => {
+            validate_oracle(deps, oraiswap_contract)?;
+        }
+        ConfigChange::SwapDenoms { denoms } => {
+            if denoms.iter().any(|denom| denom == ORAI) {
+                return Err(
+                    ContractError::Std(StdError::generic_err("ORAI is deposited without a swap"))
+                );
+            }
+        }
+        ConfigChange::LiquidityBuffer {
+            buffer_bps,
+            instant_withdraw_fee_bps,
+            penalty_recipient,
+        } => {
+            if *buffer_bps > MAX_BPS || *instant_withdraw_fee_bps > MAX_BPS {
+                return Err(
+                    ContractError::Std(StdError::generic_err("Basis points cannot exceed 10000"))
+                );
+            }
 
-        messages.push(SubMsg::new(msg));
+            if let Some(recipient) = penalty_recipient {
+                deps.api.addr_validate(recipient)?;
+            }
+        }
+        ConfigChange::RewardDistribution { distribution } => {
+            validate_reward_distribution(deps, distribution)?;
+        }
+        ConfigChange::EpochPeriod { epoch_period } => {
+            let config = CONFIG_ITEM.load(deps.storage)?;
+            validate_epoch_period(*epoch_period, config.unbonding_period)?;
+        }
+        ConfigChange::PauseFlags { .. } |
+        ConfigChange::AutoCompound { .. } |
+        ConfigChange::TimelockDelay { .. } => {}
     }
 
-    let answer = to_json_binary(
-        &(ExecuteResponse::Deposit {
-            usd_deposit: Uint128::new(user_info.usd_deposit),
-            orai_deposit: Uint128::new(user_info.orai_deposit),
-            tier: new_tier,
-            status: ResponseStatus::Success,
-        })
-    )?;
-
-    Ok(Response::new().add_submessages(messages).set_data(answer))
+    Ok(())
 }
 
-pub fn try_withdraw(deps: DepsMut, env: Env, info: MessageInfo) -> Result<Response, ContractError> {
-    let config = CONFIG_ITEM.load(deps.storage)?;
-    config.assert_contract_active()?;
-
-    let sender = info.sender.to_string();
-
-    let min_tier = config.min_tier();
-    let user_info = USER_INFOS.may_load(deps.storage, sender)?.unwrap_or(state::UserInfo {
-        tier: min_tier,
-        ..Default::default()
-    });
-
-    let amount = user_info.orai_deposit;
+fn apply_change(deps: DepsMut, change: ConfigChange) -> Result<Response, ContractError> {
+    let mut config: Config = CONFIG_ITEM.load(deps.storage)?;
+    let mut messages: Vec<SubMsg> = vec![];
 
-    USER_INFOS.remove(deps.storage, info.sender.to_string());
+    match change {
+        ConfigChange::PauseFlags { flags } => {
+            config.pause = flags;
+        }
+        ConfigChange::Deposits { deposits } => {
+            config.usd_deposits = validate_deposits(&deposits)?;
+        }
+        ConfigChange::Validators { validators } => {
+            config.validators = validators;
+            config.inactive_validators.clear();
 
-    let current_time = env.block.time.seconds();
-    let claim_time = current_time.checked_add(UNBOUND_TIME).unwrap();
-    let withdrawal = UserWithdrawal {
-        amount,
-        timestamp: current_time,
-        claim_time,
-    };
+            // Stake on validators that left the set moves to the new one
+            let removed = VALIDATOR_DELEGATIONS.keys(
+                deps.storage,
+                None,
+                None,
+                Order::Ascending
+            ).collect::<StdResult<Vec<_>>>()?;
+            for validator in removed {
+                if config.validators.iter().any(|v| v.address == validator) {
+                    continue;
+                }
 
-    let mut withdrawals = WITHDRAWALS_LIST.may_load(
-        deps.storage,
-        info.sender.to_string()
-    )?.unwrap_or_default();
+                let (redelegate_msgs, _) = redelegate_msgs(deps.storage, &config, &validator)?;
+                messages.extend(redelegate_msgs);
+            }
+        }
+        ConfigChange::Oracle { oraiswap_contract } => {
+            config.oraiswap_contract = oraiswap_contract;
+        }
+        ConfigChange::SwapDenoms { denoms } => {
+            config.swap_denoms = denoms;
+        }
+        ConfigChange::EpochPeriod { epoch_period } => {
+            config.epoch_period = epoch_period;
+        }
+        ConfigChange::LiquidityBuffer {
+            buffer_bps,
+            instant_withdraw_fee_bps,
+            penalty_recipient,
+        } => {
+            config.buffer_bps = buffer_bps;
+            config.instant_withdraw_fee_bps = instant_withdraw_fee_bps;
+            config.penalty_recipient = penalty_recipient;
+        }
+        ConfigChange::AutoCompound { enabled } => {
+            config.auto_compound = enabled;
+        }
+        ConfigChange::RewardDistribution { distribution } => {
+            config.reward_distribution = distribution;
+        }
+        ConfigChange::TimelockDelay { delay } => {
+            config.timelock_delay = delay;
+        }
+    }
 
-    withdrawals.push(withdrawal);
-    WITHDRAWALS_LIST.save(deps.storage, info.sender.to_string(), &withdrawals)?;
+    CONFIG_ITEM.save(deps.storage, &config)?;
 
-    let validators = config.validators;
-    let amount = coin(amount - 4, ORAI);
+    Ok(Response::new().add_submessages(messages).add_attribute("action", "executed change"))
+}
 
-    let mut messages: Vec<SubMsg> = Vec::with_capacity(2);
+/// Checks an epoch closes at most once per sixth of the unbonding period, so
+/// epochs leave one of the chain's unbonding entries per validator free for
+/// retries, the shutdown and cancelled withdrawals
+pub fn validate_epoch_period(epoch_period: u64, unbonding_period: u64) -> StdResult<()> {
+    if epoch_period == 0 {
+        return Err(StdError::generic_err("Epoch period must be positive"));
+    }
 
-    for validator in validators {
-        let weight_as_uint128 = Uint128::from(validator.weight);
+    let epoch_entries = MAX_UNBONDING_ENTRIES - 1;
+    if epoch_period.saturating_mul(epoch_entries) < unbonding_period {
+        return Err(StdError::generic_err("Epoch period is too short for the unbonding period"));
+    }
 
-        // Perform the multiplication - Uint128 * Uint128
-        let multiplied = amount.amount.multiply_ratio(weight_as_uint128, Uint128::from(100_u128));
+    Ok(())
+}
 
-        // Now, `multiplied` is Uint128, but we want the result as u128
-        let individual_amount: u128 = multiplied.u128();
+/// Checks oracle addresses, and that the USDT token only changes while no
+/// deposit or withdrawal is held in it since those do not record the token
+pub fn validate_oracle(
+    deps: Deps,
+    oraiswap_contract: &OraiswapContract
+) -> Result<(), ContractError> {
+    deps.api.addr_validate(&oraiswap_contract.orai_swap_router_contract)?;
+    deps.api.addr_validate(&oraiswap_contract.usdt_contract)?;
 
-        let withdraw_msg = StakingMsg::Undelegate {
-            validator: validator.address,
-            amount: coin(individual_amount, ORAI),
-        };
-        let msg = CosmosMsg::Staking(withdraw_msg);
-        messages.push(SubMsg::new(msg));
+    let config = CONFIG_ITEM.load(deps.storage)?;
+    if oraiswap_contract.usdt_contract == config.oraiswap_contract.usdt_contract {
+        return Ok(());
     }
 
-    let answer = to_json_binary(
-        &(ExecuteResponse::Withdraw {
-            status: ResponseStatus::Success,
-        })
-    )?;
+    let held_err = StdError::generic_err("USDT token cannot change while USDT is held for users");
+    for item in USER_INFOS.range(deps.storage, None, None, Order::Ascending) {
+        if item?.1.usdt_deposit != 0 {
+            return Err(ContractError::Std(held_err));
+        }
+    }
+    for item in state::withdrawals().range(deps.storage, None, None, Order::Ascending) {
+        if item?.1.token == DepositToken::Usdt {
+            return Err(ContractError::Std(held_err));
+        }
+    }
 
-    Ok(Response::new().add_submessages(messages).set_data(answer))
+    Ok(())
 }
 
-pub fn try_claim(
-    deps: DepsMut,
-    env: Env,
-    info: MessageInfo,
-    recipient: Option<String>,
-    start: Option<u32>,
-    limit: Option<u32>
-) -> Result<Response, ContractError> {
-    let config = CONFIG_ITEM.load(deps.storage)?;
-    config.assert_contract_active()?;
-
-    let sender = info.sender.to_string();
-    let mut withdrawals = WITHDRAWALS_LIST.may_load(deps.storage, sender)?.unwrap_or_default();
+/// Checks tier thresholds are set and strictly decreasing
+pub fn validate_deposits(deposits: &[Uint128]) -> Result<Vec<u128>, ContractError> {
+    let deposits = deposits
+        .iter()
+        .map(|v| v.u128())
+        .collect::<Vec<_>>();
 
-    let length = withdrawals.len();
+    if deposits.is_empty() {
+        return Err(ContractError::Std(StdError::generic_err("Deposits array is empty")));
+    }
 
-    if length == 0 {
-        return Err(ContractError::Std(StdError::generic_err("Nothing to claim")));
+    let is_sorted = deposits
+        .as_slice()
+        .windows(2)
+        .all(|v| v[0] > v[1]);
+    if !is_sorted {
+        return Err(
+            ContractError::Std(StdError::generic_err("Specify deposits in decreasing order"))
+        );
     }
 
-    let recipient = recipient.unwrap_or(info.sender.to_string());
-    let start: usize = start.unwrap_or(0) as usize;
-    let limit = limit.unwrap_or(50) as usize;
-    let withdrawals_iter: std::iter::Take<
-        std::iter::Skip<std::slice::Iter<'_, UserWithdrawal>>
-    > = withdrawals.iter().skip(start).take(limit);
+    Ok(deposits)
+}
 
-    let current_time = env.block.time.seconds();
-    let mut remove_indices = Vec::new();
-    let mut claim_amount = 0u128;
+/// Checks reward recipients are valid, listed once and that shares add up
+/// to `MAX_BPS`, an empty list keeping rewards going to a single recipient
+pub fn validate_reward_distribution(
+    deps: Deps,
+    distribution: &[RewardShare]
+) -> Result<(), ContractError> {
+    if distribution.is_empty() {
+        return Ok(());
+    }
 
-    for (index, withdrawal) in withdrawals_iter.enumerate() {
-        let claim_time = withdrawal.claim_time;
+    let mut seen: Vec<&str> = Vec::with_capacity(distribution.len());
+    let mut total: u64 = 0;
+    for share in distribution {
+        deps.api.addr_validate(&share.address)?;
 
-        if current_time >= claim_time {
-            remove_indices.push(index);
-            claim_amount = claim_amount.checked_add(withdrawal.amount).unwrap();
+        if seen.contains(&share.address.as_str()) {
+            return Err(ContractError::DuplicateRewardRecipient {
+                address: share.address.clone(),
+            });
         }
-    }
+        seen.push(&share.address);
 
-    if claim_amount == 0 {
-        return Err(ContractError::Std(StdError::generic_err("Nothing to claim")));
+        total = total.saturating_add(share.share_bps);
     }
 
-    for (shift, index) in remove_indices.into_iter().enumerate() {
-        let position = index.checked_sub(shift).unwrap();
-        withdrawals.remove(position);
+    if total != MAX_BPS {
+        return Err(ContractError::InvalidRewardShares { total });
     }
 
-    let send_msg = BankMsg::Send {
-        to_address: recipient,
-        amount: coins(claim_amount, ORAI),
-    };
+    Ok(())
+}
 
-    let msg = CosmosMsg::Bank(send_msg);
-    let answer = to_json_binary(
-        &(ExecuteResponse::Claim {
-            amount: claim_amount.into(),
-            status: ResponseStatus::Success,
-        })
-    )?;
+pub fn get_received_funds(deps: &DepsMut, info: &MessageInfo) -> Result<Coin, ContractError> {
+    match info.funds.first() {
+        None => Err(ContractError::Std(StdError::generic_err("No Funds"))),
+        Some(received) => {
+            /* Amount of tokens received cannot be zero */
+            if received.amount.is_zero() {
+                return Err(ContractError::Std(StdError::generic_err("Not Allow Zero Amount")));
+            }
+
+            /* Allow to receive only ORAI or one of the denominations
+            whitelisted in "config.swap_denoms" */
+            let config = CONFIG_ITEM.load(deps.storage)?;
+            if received.denom != ORAI && !config.swap_denoms.contains(&received.denom) {
+                return Err(ContractError::Std(StdError::generic_err("Unsopported token")));
+            }
 
-    Ok(Response::new().add_message(msg).set_data(answer))
+            /* Only one token can be received */
+            if info.funds.len() > 1 {
+                return Err(ContractError::Std(StdError::generic_err("Not Allowed Multiple Funds")));
+            }
+            Ok(received.clone())
+        }
+    }
 }
 
-pub fn try_withdraw_rewards(
+pub fn try_deposit(
     deps: DepsMut,
     env: Env,
     info: MessageInfo,
-    recipient: Option<String>
+    minimum_receive: Option<Uint128>
 ) -> Result<Response, ContractError> {
-    let config: Config = CONFIG_ITEM.load(deps.storage)?;
-    if info.sender.clone() != config.admin {
-        return Err(ContractError::Std(StdError::generic_err("Unauthorized")));
-    }
+    let config = CONFIG_ITEM.load(deps.storage)?;
+    config.assert_deposits_active()?;
 
-    let admin = config.admin;
-    let recipient = recipient.unwrap_or(admin);
-    let mut msgs: Vec<CosmosMsg> = Vec::new();
-    let set_withdraw_addr_msg = DistributionMsg::SetWithdrawAddress { address: recipient };
-    msgs.push(CosmosMsg::Distribution(set_withdraw_addr_msg));
+    let received_funds = get_received_funds(&deps, &info)?;
 
-    let mut total_withdraw_amount: u128 = 0;
+    if received_funds.denom == ORAI {
+        let sender = info.sender.to_string();
+        return deposit_orai(deps, env, config, sender, received_funds.amount.u128());
+    }
 
-    let validators = &config.validators;
-    for validator_it in validators {
-        let validator = validator_it.clone().address;
-        let delegation = utils::query_delegation(&deps, &env, &validator);
+    let minimum_receive = minimum_receive.ok_or_else(||
+        ContractError::Std(StdError::generic_err("Specify minimum_receive to swap deposit"))
+    )?;
 
-        let can_withdraw = delegation
-            .map(|d| d.unwrap().accumulated_rewards[0].amount.u128())
-            .unwrap_or(0);
+    // The tier is computed in reply, on the ORAI actually returned by the router
+    let orai_balance = deps.querier.query_balance(&env.contract.address, ORAI)?;
+    PENDING_SWAP_DEPOSIT.save(
+        deps.storage,
+        &(PendingSwapDeposit {
+            sender: info.sender.to_string(),
+            orai_balance: orai_balance.amount.u128(),
+        })
+    )?;
 
-        let withdraw_msg = DistributionMsg::WithdrawDelegatorReward { validator };
+    let swap_msg = RouterExecuteMsg::ExecuteSwapOperations {
+        operations: vec![SwapOperation::OraiSwap {
+            offer_asset_info: AssetInfo::NativeToken {
+                denom: received_funds.denom.clone(),
+            },
+            ask_asset_info: AssetInfo::NativeToken {
+                denom: ORAI.to_string(),
+            },
+        }],
+        minimum_receive: Some(minimum_receive),
+        to: None,
+    };
 
-        msgs.push(CosmosMsg::Distribution(withdraw_msg));
+    let msg = WasmMsg::Execute {
+        contract_addr: config.oraiswap_contract.orai_swap_router_contract,
+        msg: to_json_binary(&swap_msg)?,
+        funds: vec![received_funds.clone()],
+    };
 
-        total_withdraw_amount += can_withdraw;
-    }
+    Ok(
+        Response::new()
+            .add_submessage(SubMsg::reply_on_success(msg, SWAP_DEPOSIT_REPLY_ID))
+            .add_attribute("action", "swap deposit")
+            .add_attribute("offer_amount", received_funds.to_string())
+    )
+}
 
-    if total_withdraw_amount == 0 {
-        return Err(
-            ContractError::Std(
-                StdError::generic_err("There is nothing to withdraw from validators")
-            )
-        );
-    }
+pub fn reply_swap_deposit(deps: DepsMut, env: Env) -> Result<Response, ContractError> {
+    let pending = PENDING_SWAP_DEPOSIT.load(deps.storage)?;
+    PENDING_SWAP_DEPOSIT.remove(deps.storage);
 
-    let answer = to_json_binary(
-        &(ExecuteResponse::WithdrawRewards {
-            amount: Uint128::new(total_withdraw_amount),
-            status: ResponseStatus::Success,
-        })
-    )?;
+    let orai_balance = deps.querier.query_balance(&env.contract.address, ORAI)?;
+    let received = orai_balance.amount.u128().checked_sub(pending.orai_balance).unwrap();
 
-    Ok(Response::new().add_messages(msgs).set_data(answer))
-}
+    if received == 0 {
+        return Err(ContractError::Std(StdError::generic_err("Swap returned no ORAI")));
+    }
 
-fn query_config(deps: Deps) -> StdResult<QueryResponse> {
     let config = CONFIG_ITEM.load(deps.storage)?;
-    config.to_answer()
+    deposit_orai(deps, env, config, pending.sender, received)
 }
 
-pub fn query_user_info(deps: Deps, address: String) -> StdResult<QueryResponse> {
-    let config = CONFIG_ITEM.load(deps.storage)?;
+pub fn deposit_orai(
+    mut deps: DepsMut,
+    env: Env,
+    config: Config,
+    sender: String,
+    received_amount: u128
+) -> Result<Response, ContractError> {
+    let staked_amount = get_staked_amount(deps.as_ref(), &sender);
+
+    let mut orai_deposit = received_amount;
+
     // Get Tier from staking amount
-    let staked_amount = get_staked_amount(deps, &address);
-    let old_user_info = USER_INFOS.may_load(deps.storage, address.clone())?.unwrap_or(
+
+    let old_user_info = USER_INFOS.may_load(deps.storage, sender.clone())?.unwrap_or(
         state::UserInfo {
             ..Default::default()
         }
@@ -506,75 +1006,3870 @@ pub fn query_user_info(deps: Deps, address: String) -> StdResult<QueryResponse>
         .unwrap();
     let tier = config.tier_by_deposit(total_usd_deposit);
 
-    let user_info = USER_INFOS.may_load(deps.storage, address)?.unwrap_or(state::UserInfo {
-        tier,
-        ..Default::default()
-    });
+    let mut user_info = USER_INFOS.may_load(deps.storage, sender.clone())?.unwrap_or(
+        state::UserInfo {
+            tier,
+            ..Default::default()
+        }
+    );
+    //
 
-    let answer = user_info.to_answer();
-    return Ok(answer);
-}
+    // Add already staked orai and last user's orai deposit
+    orai_deposit = orai_deposit.checked_add(staked_amount.staked_orai_amount).unwrap();
 
-pub fn query_withdrawals(
-    deps: Deps,
-    address: String,
-    start: Option<u32>,
-    limit: Option<u32>
-) -> StdResult<QueryResponse> {
-    let withdrawals = WITHDRAWALS_LIST.may_load(deps.storage, address)?.unwrap_or_default();
-    let amount = withdrawals.len();
+    let orai_price_ocracle = OraiPriceOracle::new(&deps)?;
 
-    let start = start.unwrap_or(0);
-    let limit = limit.unwrap_or(50);
+    let usd_deposit: u128 = orai_price_ocracle.usd_amount(orai_deposit);
 
-    let mut serialized_withdrawals: Vec<SerializedWithdrawals> = Vec::new();
-    for i in start..start + limit {
-        let index: usize = i.try_into().unwrap();
-        if index < amount {
-            serialized_withdrawals.push(withdrawals[index].to_serialized());
+    let current_tier = user_info.tier;
+    let old_usd_deposit = user_info.usd_deposit;
+    let new_usd_deposit = old_usd_deposit.checked_add(usd_deposit).unwrap();
+
+    let new_tier = config.tier_by_deposit(new_usd_deposit);
+
+    if current_tier == new_tier {
+        if current_tier == config.max_tier() {
+            return Err(ContractError::Std(StdError::generic_err("Reached max tier")));
         }
-    }
 
-    let answer = QueryResponse::Withdrawals {
-        amount: amount.try_into().unwrap(),
-        withdrawals: serialized_withdrawals,
-    };
+        let next_tier = current_tier.checked_sub(1).unwrap();
+        let next_tier_deposit: u128 = config.deposit_by_tier(next_tier);
 
-    Ok(answer)
-}
+        let expected_deposit_usd = next_tier_deposit
+            .checked_sub(old_usd_deposit + staked_amount.staked_usd_amount)
+            .unwrap();
+        let expected_deposit_orai = orai_price_ocracle.orai_amount(expected_deposit_usd);
 
-pub struct StakedAmount {
-    staked_usd_amount: u128,
-    staked_orai_amount: u128,
-}
+        let err_msg = format!(
+            "You should deposit at least {} USD ({} orai) for {}",
+            expected_deposit_usd,
+            expected_deposit_orai,
+            orai_deposit
+        );
 
-pub fn get_staked_amount(deps: Deps, address: &str) -> StakedAmount {
-    let delegation_query = (StakingQuery::AllDelegations {
-        delegator: address.into(),
-    }).into();
+        return Err(ContractError::Std(StdError::generic_err(&err_msg)));
+    }
 
-    // Since we don't own 'deps', we can still use reference to execute queries
-    let all_delegations: AllDelegationsResponse = deps.querier.query(&delegation_query).unwrap();
+    let mut messages: Vec<SubMsg> = Vec::with_capacity(2);
+    let new_tier_deposit = config.deposit_by_tier(new_tier);
 
-    if all_delegations.delegations.is_empty() {
-        return StakedAmount {
-            staked_orai_amount: 0,
-            staked_usd_amount: 0,
+    // Stablecoins already held for the user cover part of the tier threshold
+    let usdt_usd_deposit = user_info.usdt_deposit / OraiPriceOracle::ZERO_6;
+    let new_tier_orai_deposit = orai_price_ocracle.orai_amount(
+        new_tier_deposit.saturating_sub(usdt_usd_deposit)
+    );
+
+    let old_orai_deposit = user_info.orai_deposit;
+    let orai_refund = orai_deposit
+        .checked_add(old_orai_deposit)
+        .and_then(|v| v.checked_sub(new_tier_orai_deposit))
+        .unwrap();
+
+    if orai_refund != 0 {
+        let send_msg = BankMsg::Send {
+            to_address: sender.clone(),
+            amount: coins(orai_refund, ORAI),
         };
+
+        let msg = CosmosMsg::Bank(send_msg);
+
+        messages.push(SubMsg::new(msg));
     }
+    user_info.tier = new_tier;
+    user_info.timestamp = env.block.time.seconds();
+    user_info.orai_deposit = new_tier_orai_deposit
+        .checked_sub(staked_amount.staked_orai_amount)
+        .unwrap();
+    user_info.usd_deposit =
+        orai_price_ocracle.usd_amount(user_info.orai_deposit) + 1 + usdt_usd_deposit;
 
-    let mut staked_amount_orai = Uint128::new(0);
+    // Keep a share of the deposit liquid for instant withdrawals
+    let deposit_amount = user_info.orai_deposit.checked_sub(old_orai_deposit).unwrap();
+    let buffered_amount = Uint128::from(deposit_amount)
+        .multiply_ratio(config.buffer_bps, MAX_BPS)
+        .u128();
+    let delegate_amount = deposit_amount.checked_sub(buffered_amount).unwrap();
 
-    for delegation in all_delegations.delegations {
-        staked_amount_orai += delegation.amount.amount;
+    if buffered_amount != 0 {
+        let liquidity_buffer = load_liquidity_buffer(deps.storage, env.block.time.seconds())?;
+        let liquidity_buffer = liquidity_buffer.checked_add(buffered_amount).unwrap();
+        LIQUIDITY_BUFFER.save(deps.storage, &liquidity_buffer)?;
+        user_info.orai_buffered = user_info.orai_buffered.checked_add(buffered_amount).unwrap();
     }
 
-    let orai_price_oracle = OraiPriceOracle::deps_new(&deps).unwrap();
+    sync_delegations(deps.branch(), &env)?;
+    let mut pool = STAKE_POOL.may_load(deps.storage)?.unwrap_or_default();
+    user_info.shares = user_info.shares.checked_add(pool.stake(delegate_amount)).unwrap();
+    STAKE_POOL.save(deps.storage, &pool)?;
 
-    let statked_usd_amount = orai_price_oracle.usd_amount(staked_amount_orai.into());
+    USER_INFOS.save(deps.storage, sender, &user_info)?;
 
-    return StakedAmount {
-        staked_usd_amount: u128::from(statked_usd_amount),
-        staked_orai_amount: staked_amount_orai.into(),
-    };
+    messages.extend(delegate_msgs(deps.storage, &config, delegate_amount)?);
+
+    let answer = to_json_binary(
+        &(ExecuteResponse::Deposit {
+            usd_deposit: Uint128::new(user_info.usd_deposit),
+            orai_deposit: Uint128::new(user_info.orai_deposit),
+            tier: new_tier,
+            status: ResponseStatus::Success,
+        })
+    )?;
+
+    Ok(Response::new().add_submessages(messages).set_data(answer))
+}
+
+pub fn try_receive(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    cw20_msg: Cw20ReceiveMsg
+) -> Result<Response, ContractError> {
+    let config = CONFIG_ITEM.load(deps.storage)?;
+    config.assert_deposits_active()?;
+
+    /* Only the stablecoin configured in "config.oraiswap_contract" is accepted */
+    if info.sender != config.oraiswap_contract.usdt_contract {
+        return Err(ContractError::Std(StdError::generic_err("Unsupported token")));
+    }
+
+    if cw20_msg.amount.is_zero() {
+        return Err(ContractError::Std(StdError::generic_err("Not Allow Zero Amount")));
+    }
+
+    let sender = deps.api.addr_validate(&cw20_msg.sender)?;
+
+    match from_json(&cw20_msg.msg)? {
+        Cw20HookMsg::Deposit {} => {
+            try_deposit_usdt(deps, env, config, sender.to_string(), cw20_msg.amount.u128())
+        }
+    }
+}
+
+pub fn try_deposit_usdt(
+    deps: DepsMut,
+    env: Env,
+    config: Config,
+    sender: String,
+    usdt_deposit: u128
+) -> Result<Response, ContractError> {
+    let staked_amount = get_staked_amount(deps.as_ref(), &sender);
+
+    let mut user_info = USER_INFOS.may_load(deps.storage, sender.clone())?.unwrap_or(
+        state::UserInfo {
+            tier: config.tier_by_deposit(staked_amount.staked_usd_amount),
+            ..Default::default()
+        }
+    );
+
+    // Stablecoins are counted 1:1 as USD value
+    let usd_deposit = usdt_deposit / OraiPriceOracle::ZERO_6;
+
+    let current_tier = user_info.tier;
+    let old_usd_deposit = user_info.usd_deposit
+        .checked_add(staked_amount.staked_usd_amount)
+        .unwrap();
+    let new_usd_deposit = old_usd_deposit.checked_add(usd_deposit).unwrap();
+
+    let new_tier = config.tier_by_deposit(new_usd_deposit);
+
+    if current_tier == new_tier {
+        if current_tier == config.max_tier() {
+            return Err(ContractError::Std(StdError::generic_err("Reached max tier")));
+        }
+
+        let next_tier = current_tier.checked_sub(1).unwrap();
+        let next_tier_deposit: u128 = config.deposit_by_tier(next_tier);
+
+        let expected_deposit_usd = next_tier_deposit.checked_sub(old_usd_deposit).unwrap();
+
+        let err_msg = format!(
+            "You should deposit at least {} USD ({} usdt) for {}",
+            expected_deposit_usd,
+            expected_deposit_usd.checked_mul(OraiPriceOracle::ZERO_6).unwrap(),
+            usdt_deposit
+        );
+
+        return Err(ContractError::Std(StdError::generic_err(&err_msg)));
+    }
+
+    let new_tier_deposit = config.deposit_by_tier(new_tier);
+    let usd_needed = new_tier_deposit.checked_sub(old_usd_deposit).unwrap();
+    let usdt_needed = usd_needed.checked_mul(OraiPriceOracle::ZERO_6).unwrap();
+    let usdt_refund = usdt_deposit.checked_sub(usdt_needed).unwrap();
+
+    let mut messages: Vec<CosmosMsg> = Vec::with_capacity(1);
+    if usdt_refund != 0 {
+        let usdt_contract = config.oraiswap_contract.usdt_contract;
+        messages.push(utils::cw20_transfer_msg(&usdt_contract, &sender, usdt_refund)?);
+    }
+
+    user_info.tier = new_tier;
+    user_info.timestamp = env.block.time.seconds();
+    user_info.usdt_deposit = user_info.usdt_deposit.checked_add(usdt_needed).unwrap();
+    user_info.usd_deposit = user_info.usd_deposit.checked_add(usd_needed).unwrap();
+    USER_INFOS.save(deps.storage, sender, &user_info)?;
+
+    let answer = to_json_binary(
+        &(ExecuteResponse::Deposit {
+            usd_deposit: Uint128::new(user_info.usd_deposit),
+            orai_deposit: Uint128::new(user_info.orai_deposit),
+            tier: new_tier,
+            status: ResponseStatus::Success,
+        })
+    )?;
+
+    Ok(Response::new().add_messages(messages).set_data(answer))
+}
+
+pub fn try_withdraw(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo
+) -> Result<Response, ContractError> {
+    let config = CONFIG_ITEM.load(deps.storage)?;
+    config.assert_withdrawals_active()?;
+
+    let sender = info.sender.to_string();
+
+    // The shutdown already unbonded the deposit, it only needs booking
+    if config.shutdown {
+        let user_info = USER_INFOS.may_load(deps.storage, sender.clone())?.ok_or_else(||
+            ContractError::Std(StdError::generic_err("Nothing to withdraw"))
+        )?;
+        let current_time = env.block.time.seconds();
+        withdraw_after_shutdown(deps.storage, &config, &sender, &user_info, current_time)?;
+
+        let answer = to_json_binary(
+            &(ExecuteResponse::Withdraw {
+                status: ResponseStatus::Success,
+            })
+        )?;
+
+        return Ok(Response::new().set_data(answer));
+    }
+
+    let min_tier = config.min_tier();
+    let user_info = USER_INFOS.may_load(deps.storage, sender)?.unwrap_or(state::UserInfo {
+        tier: min_tier,
+        ..Default::default()
+    });
+
+    // The staked part is worth whatever its shares are after any slashing
+    sync_delegations(deps.branch(), &env)?;
+    let mut pool = STAKE_POOL.may_load(deps.storage)?.unwrap_or_default();
+    let staked = pool.unstake(user_info.shares);
+    STAKE_POOL.save(deps.storage, &pool)?;
+    let amount = user_info.orai_buffered.checked_add(staked).unwrap();
+
+    USER_INFOS.remove(deps.storage, info.sender.to_string());
+
+    let current_time = env.block.time.seconds();
+    let withdrawals = state::withdrawals();
+
+    // The buffered part of the deposit is reserved for the claim
+    let (epoch_id, mut epoch) = load_current_epoch(deps.storage, current_time)?;
+    let (refilled, from_epoch) = reserve_buffered(
+        deps.storage,
+        current_time,
+        user_info.orai_buffered,
+        Some(&mut epoch)
+    )?;
+
+    if amount != 0 {
+        // The staked part is unbonded together with the rest of the epoch
+        let undelegated = staked.checked_add(from_epoch).unwrap();
+        let epoch_shares = epoch.queue_undelegation(undelegated);
+        EPOCHS.save(deps.storage, epoch_id, &epoch)?;
+
+        let claim_time = epoch.start_time
+            .checked_add(config.epoch_period)
+            .and_then(|v| v.checked_add(config.unbonding_period))
+            .unwrap();
+        let id = state::next_withdrawal_id(deps.storage)?;
+        withdrawals.save(
+            deps.storage,
+            (info.sender.to_string(), id),
+            &(UserWithdrawal {
+                amount,
+                timestamp: current_time,
+                claim_time,
+                unbonding_period: config.unbonding_period,
+                token: DepositToken::Orai,
+                epoch: Some(epoch_id),
+                undelegated,
+                refilled,
+                epoch_shares,
+            })
+        )?;
+    }
+
+    // Stablecoins are not staked, so they can be claimed right away
+    if user_info.usdt_deposit != 0 {
+        let id = state::next_withdrawal_id(deps.storage)?;
+        withdrawals.save(
+            deps.storage,
+            (info.sender.to_string(), id),
+            &(UserWithdrawal {
+                amount: user_info.usdt_deposit,
+                timestamp: current_time,
+                claim_time: current_time,
+                unbonding_period: 0,
+                token: DepositToken::Usdt,
+                epoch: None,
+                undelegated: 0,
+                refilled: 0,
+                epoch_shares: 0,
+            })
+        )?;
+    }
+
+    let answer = to_json_binary(
+        &(ExecuteResponse::Withdraw {
+            status: ResponseStatus::Success,
+        })
+    )?;
+
+    Ok(Response::new().set_data(answer))
+}
+
+pub fn try_instant_withdraw(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo
+) -> Result<Response, ContractError> {
+    let config = CONFIG_ITEM.load(deps.storage)?;
+    config.assert_withdrawals_active()?;
+
+    // Nothing is left to refill the buffer from
+    if config.shutdown {
+        return Err(ContractError::Std(StdError::generic_err("Contract is shut down")));
+    }
+
+    let sender = info.sender.to_string();
+    let user_info = USER_INFOS.may_load(deps.storage, sender.clone())?.ok_or_else(||
+        ContractError::Std(StdError::generic_err("Nothing to withdraw"))
+    )?;
+
+    sync_delegations(deps.branch(), &env)?;
+    let mut pool = STAKE_POOL.may_load(deps.storage)?.unwrap_or_default();
+    let staked = pool.unstake(user_info.shares);
+    STAKE_POOL.save(deps.storage, &pool)?;
+
+    let amount = user_info.orai_buffered.checked_add(staked).unwrap();
+    let penalty = Uint128::from(amount)
+        .multiply_ratio(config.instant_withdraw_fee_bps, MAX_BPS)
+        .u128();
+    let payout = amount.checked_sub(penalty).unwrap();
+
+    let current_time = env.block.time.seconds();
+    let liquidity_buffer = load_liquidity_buffer(deps.storage, current_time)?;
+    if liquidity_buffer < amount {
+        return Err(
+            ContractError::Std(StdError::generic_err("Not enough liquidity for instant withdraw"))
+        );
+    }
+    LIQUIDITY_BUFFER.save(deps.storage, &(liquidity_buffer - amount))?;
+
+    USER_INFOS.remove(deps.storage, sender.clone());
+
+    // Unbond the staked part of the deposit with the epoch to refill the buffer
+    let refill_amount = staked;
+    if refill_amount != 0 {
+        let (epoch_id, mut epoch) = load_current_epoch(deps.storage, current_time)?;
+        epoch.refill_amount = epoch.refill_amount.checked_add(refill_amount).unwrap();
+        EPOCHS.save(deps.storage, epoch_id, &epoch)?;
+    }
+
+    let mut messages: Vec<SubMsg> = Vec::with_capacity(3);
+
+    if payout != 0 {
+        messages.push(
+            SubMsg::new(BankMsg::Send {
+                to_address: sender.clone(),
+                amount: coins(payout, ORAI),
+            })
+        );
+    }
+
+    // Without a penalty recipient the penalty is staked for the remaining stakers
+    match config.penalty_recipient.clone() {
+        Some(penalty_recipient) if penalty != 0 => {
+            messages.push(
+                SubMsg::new(BankMsg::Send {
+                    to_address: penalty_recipient,
+                    amount: coins(penalty, ORAI),
+                })
+            );
+        }
+        None => messages.extend(compound_msgs(deps.storage, &config, penalty)?),
+        _ => {}
+    }
+
+    if user_info.usdt_deposit != 0 {
+        let usdt_contract = config.oraiswap_contract.usdt_contract;
+        messages.push(
+            SubMsg::new(utils::cw20_transfer_msg(&usdt_contract, &sender, user_info.usdt_deposit)?)
+        );
+    }
+
+    let answer = to_json_binary(
+        &(ExecuteResponse::InstantWithdraw {
+            amount: Uint128::new(payout),
+            penalty: Uint128::new(penalty),
+            usdt_amount: Uint128::new(user_info.usdt_deposit),
+            status: ResponseStatus::Success,
+        })
+    )?;
+
+    Ok(Response::new().add_submessages(messages).set_data(answer))
+}
+
+pub fn try_cancel_withdrawal(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    id: u64
+) -> Result<Response, ContractError> {
+    let config = CONFIG_ITEM.load(deps.storage)?;
+    config.assert_deposits_active()?;
+
+    let sender = info.sender.to_string();
+    let withdrawals = state::withdrawals();
+    let withdrawal = withdrawals.may_load(deps.storage, (sender.clone(), id))?.ok_or_else(||
+        ContractError::Std(StdError::generic_err("Withdrawal not found"))
+    )?;
+
+    let current_time = env.block.time.seconds();
+    let epoch_id = withdrawal.epoch.ok_or_else(||
+        ContractError::Std(StdError::generic_err("Withdrawal is not unbonding"))
+    )?;
+
+    let mut epoch = EPOCHS.load(deps.storage, epoch_id)?;
+    if epoch.processed && current_time >= epoch.claim_time {
+        return Err(ContractError::Std(StdError::generic_err("Withdrawal is not unbonding")));
+    }
+
+    withdrawals.remove(deps.storage, (sender.clone(), id))?;
+
+    let mut user_info = USER_INFOS.may_load(deps.storage, sender.clone())?.unwrap_or_default();
+    let mut messages: Vec<SubMsg> = Vec::with_capacity(epoch.undelegations.len());
+
+    // What is left of the undelegated part after any slashing loss
+    let undelegated = epoch.dequeue_undelegation(withdrawal.epoch_shares());
+    EPOCHS.save(deps.storage, epoch_id, &epoch)?;
+
+    if epoch.processed && undelegated != 0 {
+        // Cancel the withdrawal's share of the epoch unbonding entries
+        let contract_address = env.contract.address.to_string();
+        let cancelled = take_pro_rata(&mut epoch.undelegations, undelegated);
+        EPOCHS.save(deps.storage, epoch_id, &epoch)?;
+
+        for undelegation in cancelled {
+            VALIDATOR_DELEGATIONS.update(
+                deps.storage,
+                undelegation.validator.clone(),
+                |delegated| -> StdResult<_> {
+                    Ok(delegated.unwrap_or_default().checked_add(undelegation.amount).unwrap())
+                }
+            )?;
+
+            // Delegating back withdraws the validator's pending rewards
+            let cancel_msg = utils::cancel_unbonding_msg(
+                &contract_address,
+                &undelegation.validator,
+                undelegation.amount,
+                undelegation.creation_height.unwrap_or(epoch.creation_height)
+            );
+            messages.push(SubMsg::reply_on_success(cancel_msg, STAKING_REWARDS_REPLY_ID));
+        }
+    }
+
+    // Whatever was not undelegated was reserved from the buffer or its refills
+    let buffered = withdrawal.amount
+        .checked_sub(withdrawal.undelegated)
+        .and_then(|v| v.checked_sub(withdrawal.refilled))
+        .unwrap();
+    if buffered != 0 {
+        let liquidity_buffer = load_liquidity_buffer(deps.storage, current_time)?;
+        let liquidity_buffer = liquidity_buffer.checked_add(buffered).unwrap();
+        LIQUIDITY_BUFFER.save(deps.storage, &liquidity_buffer)?;
+    }
+
+    // Reserved refills have matured by the claim time at the latest
+    if withdrawal.refilled != 0 {
+        BUFFER_REFILLS.update(
+            deps.storage,
+            withdrawal.claim_time,
+            |refill| -> StdResult<_> {
+                Ok(refill.unwrap_or_default().checked_add(withdrawal.refilled).unwrap())
+            }
+        )?;
+    }
+
+    // The USDT part of the deposit was booked right after the ORAI part and
+    // goes back too, unless it has been claimed already
+    let usdt_id = id.checked_add(1).unwrap();
+    let usdt_withdrawal = withdrawals
+        .may_load(deps.storage, (sender.clone(), usdt_id))?
+        .filter(|w| w.token == DepositToken::Usdt && w.timestamp == withdrawal.timestamp);
+    if let Some(usdt_withdrawal) = usdt_withdrawal {
+        withdrawals.remove(deps.storage, (sender.clone(), usdt_id))?;
+        user_info.usdt_deposit = user_info.usdt_deposit
+            .checked_add(usdt_withdrawal.amount)
+            .unwrap();
+    }
+
+    let orai_buffered = buffered.checked_add(withdrawal.refilled).unwrap();
+    let orai_deposit = orai_buffered.checked_add(undelegated).unwrap();
+    user_info.orai_deposit = user_info.orai_deposit.checked_add(orai_deposit).unwrap();
+    user_info.orai_buffered = user_info.orai_buffered.checked_add(orai_buffered).unwrap();
+
+    // Unbonding ORAI goes back to the pool at the current rate
+    let mut pool = STAKE_POOL.may_load(deps.storage)?.unwrap_or_default();
+    let shares = pool.stake(undelegated);
+    user_info.shares = user_info.shares.checked_add(shares).unwrap();
+    STAKE_POOL.save(deps.storage, &pool)?;
+
+    // Re-evaluate the tier at today's price
+    let staked_amount = get_staked_amount(deps.as_ref(), &sender);
+    let orai_price_oracle = OraiPriceOracle::new(&deps)?;
+    user_info.usd_deposit = orai_price_oracle
+        .usd_amount(user_info.orai_deposit)
+        .checked_add(user_info.usdt_deposit / OraiPriceOracle::ZERO_6)
+        .unwrap();
+    user_info.tier = config.tier_by_deposit(
+        user_info.usd_deposit.checked_add(staked_amount.staked_usd_amount).unwrap()
+    );
+    user_info.timestamp = current_time;
+    USER_INFOS.save(deps.storage, sender, &user_info)?;
+
+    let answer = to_json_binary(
+        &(ExecuteResponse::CancelWithdrawal {
+            usd_deposit: Uint128::new(user_info.usd_deposit),
+            orai_deposit: Uint128::new(user_info.orai_deposit),
+            tier: user_info.tier,
+            status: ResponseStatus::Success,
+        })
+    )?;
+
+    Ok(Response::new().add_submessages(messages).set_data(answer))
+}
+
+pub fn try_claim(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    recipient: Option<String>,
+    limit: Option<u32>
+) -> Result<Response, ContractError> {
+    let config = CONFIG_ITEM.load(deps.storage)?;
+    config.assert_claims_active()?;
+
+    let sender = info.sender.to_string();
+    let recipient = recipient.unwrap_or(info.sender.to_string());
+    let limit = limit.unwrap_or(50) as usize;
+
+    let current_time = env.block.time.seconds();
+    let (claim_amount, usdt_claim_amount, _) = claim_matured_withdrawals(
+        deps.storage,
+        &sender,
+        current_time,
+        limit
+    )?;
+
+    if claim_amount == 0 && usdt_claim_amount == 0 {
+        return Err(ContractError::Std(StdError::generic_err("Nothing to claim")));
+    }
+
+    let msgs = claim_msgs(&config, &recipient, claim_amount, usdt_claim_amount)?;
+
+    let answer = to_json_binary(
+        &(ExecuteResponse::Claim {
+            amount: claim_amount.into(),
+            usdt_amount: usdt_claim_amount.into(),
+            status: ResponseStatus::Success,
+        })
+    )?;
+
+    Ok(Response::new().add_messages(msgs).set_data(answer))
+}
+
+pub fn try_claim_for(
+    deps: DepsMut,
+    env: Env,
+    addresses: Vec<String>,
+    limit: Option<u32>
+) -> Result<Response, ContractError> {
+    let config = CONFIG_ITEM.load(deps.storage)?;
+    config.assert_claims_active()?;
+
+    // "limit" bounds the withdrawals read across all addresses
+    let mut remaining = limit.unwrap_or(50) as usize;
+    let current_time = env.block.time.seconds();
+
+    let mut msgs: Vec<CosmosMsg> = Vec::new();
+    let mut attributes = Vec::new();
+    let mut users = 0u32;
+    let mut total_claim_amount = 0u128;
+    let mut total_usdt_claim_amount = 0u128;
+
+    for address in addresses {
+        if remaining == 0 {
+            break;
+        }
+
+        let user = deps.api.addr_validate(&address)?.to_string();
+        let (claim_amount, usdt_claim_amount, scanned) = claim_matured_withdrawals(
+            deps.storage,
+            &user,
+            current_time,
+            remaining
+        )?;
+        remaining = remaining.saturating_sub(scanned);
+
+        if claim_amount == 0 && usdt_claim_amount == 0 {
+            continue;
+        }
+
+        // Funds only ever go to the owner of the withdrawals
+        msgs.extend(claim_msgs(&config, &user, claim_amount, usdt_claim_amount)?);
+        attributes.push(("claimed_for", user));
+        users += 1;
+        total_claim_amount = total_claim_amount.checked_add(claim_amount).unwrap();
+        total_usdt_claim_amount = total_usdt_claim_amount.checked_add(usdt_claim_amount).unwrap();
+    }
+
+    let answer = to_json_binary(
+        &(ExecuteResponse::ClaimFor {
+            users,
+            amount: total_claim_amount.into(),
+            usdt_amount: total_usdt_claim_amount.into(),
+            status: ResponseStatus::Success,
+        })
+    )?;
+
+    Ok(Response::new().add_messages(msgs).add_attributes(attributes).set_data(answer))
+}
+
+fn claim_msgs(
+    config: &Config,
+    recipient: &str,
+    claim_amount: u128,
+    usdt_claim_amount: u128
+) -> StdResult<Vec<CosmosMsg>> {
+    let mut msgs: Vec<CosmosMsg> = Vec::with_capacity(2);
+    if claim_amount != 0 {
+        let send_msg = BankMsg::Send {
+            to_address: recipient.to_string(),
+            amount: coins(claim_amount, ORAI),
+        };
+
+        msgs.push(CosmosMsg::Bank(send_msg));
+    }
+
+    if usdt_claim_amount != 0 {
+        let usdt_contract = &config.oraiswap_contract.usdt_contract;
+        msgs.push(utils::cw20_transfer_msg(usdt_contract, recipient, usdt_claim_amount)?);
+    }
+
+    Ok(msgs)
+}
+
+pub fn try_process_epoch(mut deps: DepsMut, env: Env) -> Result<Response, ContractError> {
+    let config = CONFIG_ITEM.load(deps.storage)?;
+    sync_delegations(deps.branch(), &env)?;
+
+    let current_time = env.block.time.seconds();
+    let (epoch_id, mut epoch) = load_current_epoch(deps.storage, current_time)?;
+
+    let end_time = epoch.start_time.checked_add(config.epoch_period).unwrap();
+    if current_time < end_time {
+        let err_msg = format!("Epoch {} can be processed after {}", epoch_id, end_time);
+        return Err(ContractError::Std(StdError::generic_err(&err_msg)));
+    }
+
+    // One undelegation per validator for everything queued during the epoch
+    let amount = epoch.undelegate_amount.checked_add(epoch.refill_amount).unwrap();
+    let (messages, undelegations) = undelegate_msgs(deps.storage, &config, amount, epoch_id)?;
+
+    epoch.processed = true;
+    epoch.creation_height = env.block.height;
+    epoch.claim_time = current_time.checked_add(config.unbonding_period).unwrap();
+    epoch.undelegations = undelegations;
+    EPOCHS.save(deps.storage, epoch_id, &epoch)?;
+
+    if epoch.refill_amount != 0 {
+        BUFFER_REFILLS.update(
+            deps.storage,
+            epoch.claim_time,
+            |refill| -> StdResult<_> {
+                Ok(refill.unwrap_or_default().checked_add(epoch.refill_amount).unwrap())
+            }
+        )?;
+    }
+
+    let next_epoch_id = epoch_id.checked_add(1).unwrap();
+    CURRENT_EPOCH.save(deps.storage, &next_epoch_id)?;
+    EPOCHS.save(
+        deps.storage,
+        next_epoch_id,
+        &(Epoch {
+            start_time: current_time,
+            ..Default::default()
+        })
+    )?;
+
+    let answer = to_json_binary(
+        &(ExecuteResponse::ProcessEpoch {
+            epoch: epoch_id,
+            amount: Uint128::new(amount),
+            status: ResponseStatus::Success,
+        })
+    )?;
+
+    Ok(Response::new().add_submessages(messages).set_data(answer))
+}
+
+/// Undelegates everything from every validator in the current epoch, which is
+/// processed right away. Deposits are turned into withdrawals from that epoch
+/// by `ProcessShutdown` or when their owners withdraw.
+pub fn try_emergency_shutdown(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo
+) -> Result<Response, ContractError> {
+    let mut config = CONFIG_ITEM.load(deps.storage)?;
+    config.assert_role(deps.storage, info.sender.as_str(), Role::Owner)?;
+
+    if config.shutdown {
+        return Err(ContractError::Std(StdError::generic_err("Contract is shut down")));
+    }
+
+    sync_delegations(deps.branch(), &env)?;
+
+    let current_time = env.block.time.seconds();
+    let (epoch_id, mut epoch) = load_current_epoch(deps.storage, current_time)?;
+    let claim_time = current_time.checked_add(config.unbonding_period).unwrap();
+
+    // Stake whose undelegation failed is undelegated below with everything else,
+    // its epoch can be claimed together with this one
+    let failed_undelegations = FAILED_UNDELEGATIONS.range(
+        deps.storage,
+        None,
+        None,
+        Order::Ascending
+    ).collect::<StdResult<Vec<_>>>()?;
+    for (failed_epoch_id, _) in failed_undelegations {
+        FAILED_UNDELEGATIONS.remove(deps.storage, failed_epoch_id);
+        let mut failed_epoch = EPOCHS.load(deps.storage, failed_epoch_id)?;
+        failed_epoch.claim_time = failed_epoch.claim_time.max(claim_time);
+        EPOCHS.save(deps.storage, failed_epoch_id, &failed_epoch)?;
+    }
+
+    // ORAI that failed to delegate is already liquid and stays for withdrawals,
+    // as does stake unbonding to be redelegated once it matures. Stake that
+    // failed to redelegate is tracked on its validator and undelegated below.
+    PENDING_DELEGATION.remove(deps.storage);
+    UNBONDING_REDELEGATIONS.clear(deps.storage);
+    FAILED_REDELEGATIONS.clear(deps.storage);
+
+    // Staked deposits are unbonded here, their withdrawals are booked later
+    let pool = STAKE_POOL.may_load(deps.storage)?.unwrap_or_default();
+    epoch.queue_undelegation(pool.orai);
+
+    // Compounded rewards left on validators are unbonded along with the deposits
+    let amount = VALIDATOR_DELEGATIONS.range(deps.storage, None, None, Order::Ascending)
+        .map(|item| item.map(|(_, delegated)| delegated))
+        .sum::<StdResult<u128>>()?;
+    let (messages, undelegations) = undelegate_msgs(deps.storage, &config, amount, epoch_id)?;
+
+    epoch.processed = true;
+    epoch.creation_height = env.block.height;
+    epoch.claim_time = claim_time;
+    epoch.undelegations = undelegations;
+    EPOCHS.save(deps.storage, epoch_id, &epoch)?;
+
+    if epoch.refill_amount != 0 {
+        BUFFER_REFILLS.update(
+            deps.storage,
+            epoch.claim_time,
+            |refill| -> StdResult<_> {
+                Ok(refill.unwrap_or_default().checked_add(epoch.refill_amount).unwrap())
+            }
+        )?;
+    }
+
+    let next_epoch_id = epoch_id.checked_add(1).unwrap();
+    CURRENT_EPOCH.save(deps.storage, &next_epoch_id)?;
+    EPOCHS.save(
+        deps.storage,
+        next_epoch_id,
+        &(Epoch {
+            start_time: current_time,
+            ..Default::default()
+        })
+    )?;
+
+    config.shutdown = true;
+    config.pause.deposits = true;
+    config.auto_compound = false;
+    CONFIG_ITEM.save(deps.storage, &config)?;
+    SHUTDOWN_EPOCH.save(deps.storage, &epoch_id)?;
+
+    let answer = to_json_binary(
+        &(ExecuteResponse::EmergencyShutdown {
+            epoch: epoch_id,
+            amount: Uint128::new(amount),
+            status: ResponseStatus::Success,
+        })
+    )?;
+
+    Ok(Response::new().add_submessages(messages).set_data(answer))
+}
+
+/// Turns up to `limit` deposits into withdrawals after a shutdown, callable by anyone
+pub fn try_process_shutdown(
+    deps: DepsMut,
+    env: Env,
+    limit: Option<u32>
+) -> Result<Response, ContractError> {
+    let config = CONFIG_ITEM.load(deps.storage)?;
+    if !config.shutdown {
+        return Err(ContractError::Std(StdError::generic_err("Contract is not shut down")));
+    }
+
+    // Converted deposits are removed, so each call starts from the first one left
+    let limit = limit.unwrap_or(50) as usize;
+    let users = USER_INFOS.range(deps.storage, None, None, Order::Ascending)
+        .take(limit)
+        .collect::<StdResult<Vec<_>>>()?;
+
+    if users.is_empty() {
+        return Err(ContractError::Std(StdError::generic_err("Nothing to process")));
+    }
+
+    let current_time = env.block.time.seconds();
+    for (address, user_info) in users.iter() {
+        withdraw_after_shutdown(deps.storage, &config, address, user_info, current_time)?;
+    }
+
+    let answer = to_json_binary(
+        &(ExecuteResponse::ProcessShutdown {
+            users: users.len() as u64,
+            status: ResponseStatus::Success,
+        })
+    )?;
+
+    Ok(Response::new().set_data(answer))
+}
+
+/// Books a deposit as withdrawals maturing with the shutdown epoch, whose
+/// undelegation already covers the staked part
+fn withdraw_after_shutdown(
+    storage: &mut dyn Storage,
+    config: &Config,
+    address: &str,
+    user_info: &state::UserInfo,
+    current_time: u64
+) -> StdResult<()> {
+    let epoch_id = SHUTDOWN_EPOCH.load(storage)?;
+    let epoch = EPOCHS.load(storage, epoch_id)?;
+    let withdrawals = state::withdrawals();
+
+    let mut pool = STAKE_POOL.may_load(storage)?.unwrap_or_default();
+    let staked = pool.unstake(user_info.shares);
+    STAKE_POOL.save(storage, &pool)?;
+    let amount = user_info.orai_buffered.checked_add(staked).unwrap();
+
+    let (refilled, _) = reserve_buffered(storage, current_time, user_info.orai_buffered, None)?;
+
+    if amount != 0 {
+        let id = state::next_withdrawal_id(storage)?;
+        withdrawals.save(
+            storage,
+            (address.to_string(), id),
+            &(UserWithdrawal {
+                amount,
+                timestamp: current_time,
+                claim_time: epoch.claim_time,
+                unbonding_period: config.unbonding_period,
+                token: DepositToken::Orai,
+                epoch: Some(epoch_id),
+                undelegated: staked,
+                refilled,
+                epoch_shares: epoch.undelegation_shares_for(staked),
+            })
+        )?;
+    }
+
+    if user_info.usdt_deposit != 0 {
+        let id = state::next_withdrawal_id(storage)?;
+        withdrawals.save(
+            storage,
+            (address.to_string(), id),
+            &(UserWithdrawal {
+                amount: user_info.usdt_deposit,
+                timestamp: current_time,
+                claim_time: current_time,
+                unbonding_period: 0,
+                token: DepositToken::Usdt,
+                epoch: None,
+                undelegated: 0,
+                refilled: 0,
+                epoch_shares: 0,
+            })
+        )?;
+    }
+
+    USER_INFOS.remove(storage, address.to_string());
+
+    Ok(())
+}
+
+pub fn try_withdraw_rewards(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    recipient: Option<String>,
+    swap_minimum_price: Option<Decimal>
+) -> Result<Response, ContractError> {
+    let config: Config = CONFIG_ITEM.load(deps.storage)?;
+    config.assert_role(deps.storage, info.sender.as_str(), Role::RewardManager)?;
+    config.assert_rewards_active()?;
+
+    if config.auto_compound {
+        return Err(ContractError::Std(StdError::generic_err("Rewards are auto-compounded")));
+    }
+
+    // The stakers' share would be delegated again
+    let contract_address = env.contract.address.to_string();
+    if config.shutdown && config.reward_distribution.iter().any(|r| r.address == contract_address) {
+        return Err(ContractError::Std(StdError::generic_err("Contract is shut down")));
+    }
+
+    if recipient.is_some() && !config.reward_distribution.is_empty() {
+        return Err(
+            ContractError::Std(StdError::generic_err("Rewards follow the configured distribution"))
+        );
+    }
+
+    if swap_minimum_price.is_some_and(|price| price.is_zero()) {
+        return Err(ContractError::Std(StdError::generic_err("Specify a non-zero minimum price")));
+    }
+
+    // Rewards to split or swap are paid from the contract once they land
+    if !config.reward_distribution.is_empty() || swap_minimum_price.is_some() {
+        let recipient = recipient
+            .map(|recipient| deps.api.addr_validate(&recipient))
+            .transpose()?
+            .map(|recipient| recipient.to_string());
+        let pending = PendingRewards {
+            orai_balance: 0,
+            recipient,
+            swap_minimum_price,
+        };
+
+        return withdraw_rewards_to_contract(
+            deps,
+            &env,
+            &config,
+            pending,
+            WITHDRAW_REWARDS_REPLY_ID
+        );
+    }
+
+    let admin = config.admin;
+    let recipient = recipient.unwrap_or(admin);
+    let mut msgs: Vec<CosmosMsg> = Vec::new();
+    let set_withdraw_addr_msg = DistributionMsg::SetWithdrawAddress { address: recipient };
+    msgs.push(CosmosMsg::Distribution(set_withdraw_addr_msg));
+    REWARDS_TO_CONTRACT.save(deps.storage, &false)?;
+
+    let mut total_withdraw_amount: u128 = 0;
+
+    let validators = &config.validators;
+    for validator_it in validators {
+        let validator = validator_it.clone().address;
+        // Stake moved away from an inactive validator leaves nothing to withdraw
+        let Some(delegation) = utils::query_delegation(deps.as_ref(), &env, &validator)? else {
+            continue;
+        };
+
+        let can_withdraw = delegation.accumulated_rewards
+            .first()
+            .map(|c| c.amount.u128())
+            .unwrap_or(0);
+
+        let withdraw_msg = DistributionMsg::WithdrawDelegatorReward { validator };
+
+        msgs.push(CosmosMsg::Distribution(withdraw_msg));
+
+        total_withdraw_amount += can_withdraw;
+    }
+
+    if total_withdraw_amount == 0 {
+        return Err(
+            ContractError::Std(
+                StdError::generic_err("There is nothing to withdraw from validators")
+            )
+        );
+    }
+
+    let answer = to_json_binary(
+        &(ExecuteResponse::WithdrawRewards {
+            amount: Uint128::new(total_withdraw_amount),
+            usdt_amount: Uint128::zero(),
+            status: ResponseStatus::Success,
+        })
+    )?;
+
+    Ok(Response::new().add_messages(msgs).set_data(answer))
+}
+
+/// Withdraws rewards from every validator to the contract, `reply_compound`
+/// then delegates them back into the stake pool
+pub fn try_compound(deps: DepsMut, env: Env) -> Result<Response, ContractError> {
+    let config: Config = CONFIG_ITEM.load(deps.storage)?;
+    config.assert_rewards_active()?;
+    if config.shutdown {
+        return Err(ContractError::Std(StdError::generic_err("Contract is shut down")));
+    }
+
+    if !config.auto_compound {
+        return Err(ContractError::Std(StdError::generic_err("Auto compound is disabled")));
+    }
+
+    let pending = PendingRewards {
+        orai_balance: 0,
+        recipient: None,
+        swap_minimum_price: None,
+    };
+    withdraw_rewards_to_contract(deps, &env, &config, pending, COMPOUND_REPLY_ID)
+}
+
+/// Withdraws ORAI rewards from every validator to the contract itself,
+/// replying with `reply_id` once the last withdrawal has landed
+fn withdraw_rewards_to_contract(
+    deps: DepsMut,
+    env: &Env,
+    config: &Config,
+    mut pending: PendingRewards,
+    reply_id: u64
+) -> Result<Response, ContractError> {
+    let mut msgs: Vec<CosmosMsg> = Vec::new();
+    let set_withdraw_addr_msg = DistributionMsg::SetWithdrawAddress {
+        address: env.contract.address.to_string(),
+    };
+    msgs.push(CosmosMsg::Distribution(set_withdraw_addr_msg));
+
+    let mut total_rewards: u128 = 0;
+    for validator_it in config.validators.iter() {
+        let validator = validator_it.address.clone();
+        let Some(delegation) = utils::query_delegation(deps.as_ref(), env, &validator)? else {
+            continue;
+        };
+
+        let rewards = delegation.accumulated_rewards
+            .iter()
+            .find(|c| c.denom == ORAI)
+            .map(|c| c.amount.u128())
+            .unwrap_or(0);
+        if rewards == 0 {
+            continue;
+        }
+
+        let withdraw_msg = DistributionMsg::WithdrawDelegatorReward { validator };
+        msgs.push(CosmosMsg::Distribution(withdraw_msg));
+        total_rewards = total_rewards.checked_add(rewards).unwrap();
+    }
+
+    let auto_withdrawn = AUTO_WITHDRAWN_REWARDS.may_load(deps.storage)?.unwrap_or_default();
+    if total_rewards == 0 && auto_withdrawn == 0 {
+        return Err(
+            ContractError::Std(
+                StdError::generic_err("There is nothing to withdraw from validators")
+            )
+        );
+    }
+
+    let orai_balance = deps.querier.query_balance(&env.contract.address, ORAI)?;
+    pending.orai_balance = orai_balance.amount.u128();
+    PENDING_REWARDS.save(deps.storage, &pending)?;
+    REWARDS_TO_CONTRACT.save(deps.storage, &true)?;
+
+    // Reply once the last withdrawal has landed
+    let last_msg = msgs.pop().unwrap();
+
+    Ok(
+        Response::new()
+            .add_messages(msgs)
+            .add_submessage(SubMsg::reply_on_success(last_msg, reply_id))
+    )
+}
+
+pub fn reply_compound(mut deps: DepsMut, env: Env) -> Result<Response, ContractError> {
+    let (amount, _) = take_withdrawn_rewards(deps.branch(), &env)?;
+
+    let config = CONFIG_ITEM.load(deps.storage)?;
+    let messages = compound_msgs(deps.storage, &config, amount)?;
+
+    let answer = to_json_binary(
+        &(ExecuteResponse::Compound {
+            amount: Uint128::new(amount),
+            status: ResponseStatus::Success,
+        })
+    )?;
+
+    Ok(
+        Response::new()
+            .add_submessages(messages)
+            .add_attribute("action", "compound")
+            .add_attribute("amount", amount.to_string())
+            .set_data(answer)
+    )
+}
+
+pub fn reply_withdraw_rewards(mut deps: DepsMut, env: Env) -> Result<Response, ContractError> {
+    let (amount, pending) = take_withdrawn_rewards(deps.branch(), &env)?;
+
+    let config = CONFIG_ITEM.load(deps.storage)?;
+    let contract_address = env.contract.address.to_string();
+
+    let payouts = if config.reward_distribution.is_empty() {
+        vec![(pending.recipient.unwrap_or(config.admin.clone()), amount)]
+    } else {
+        config.split_rewards(amount)
+    };
+
+    let mut messages: Vec<SubMsg> = vec![];
+    let mut attributes: Vec<(String, String)> = vec![];
+    let mut swap_payouts: Vec<(String, u128)> = vec![];
+    for (address, share_amount) in payouts {
+        attributes.push((address.clone(), share_amount.to_string()));
+        if share_amount == 0 {
+            continue;
+        }
+
+        // The stakers' share goes back into the pool
+        if address == contract_address {
+            messages.extend(compound_msgs(deps.storage, &config, share_amount)?);
+        } else if pending.swap_minimum_price.is_some() {
+            swap_payouts.push((address, share_amount));
+        } else {
+            messages.push(
+                SubMsg::new(BankMsg::Send {
+                    to_address: address,
+                    amount: coins(share_amount, ORAI),
+                })
+            );
+        }
+    }
+
+    let swap_amount: u128 = swap_payouts
+        .iter()
+        .map(|(_, a)| a)
+        .sum();
+    if let Some(minimum_price) = pending.swap_minimum_price.filter(|_| swap_amount != 0) {
+        // The price comes from the caller, the pool itself can be moved in the same block
+        let minimum_receive = Uint128::new(swap_amount) * minimum_price;
+
+        let usdt_contract = config.oraiswap_contract.usdt_contract.clone();
+        let usdt_balance = utils::query_cw20_balance(
+            deps.as_ref(),
+            &usdt_contract,
+            &contract_address
+        )?;
+        PENDING_REWARD_SWAP.save(
+            deps.storage,
+            &(PendingRewardSwap {
+                amount,
+                usdt_balance,
+                payouts: swap_payouts,
+            })
+        )?;
+
+        let swap_msg = RouterExecuteMsg::ExecuteSwapOperations {
+            operations: vec![SwapOperation::OraiSwap {
+                offer_asset_info: AssetInfo::NativeToken {
+                    denom: ORAI.to_string(),
+                },
+                ask_asset_info: AssetInfo::Token {
+                    contract_addr: usdt_contract,
+                },
+            }],
+            minimum_receive: Some(minimum_receive),
+            to: None,
+        };
+        let msg = WasmMsg::Execute {
+            contract_addr: config.oraiswap_contract.orai_swap_router_contract,
+            msg: to_json_binary(&swap_msg)?,
+            funds: coins(swap_amount, ORAI),
+        };
+        messages.push(SubMsg::reply_on_success(msg, SWAP_REWARDS_REPLY_ID));
+    }
+
+    let answer = to_json_binary(
+        &(ExecuteResponse::WithdrawRewards {
+            amount: Uint128::new(amount),
+            usdt_amount: Uint128::zero(),
+            status: ResponseStatus::Success,
+        })
+    )?;
+
+    Ok(
+        Response::new()
+            .add_submessages(messages)
+            .add_attribute("action", "distributed rewards")
+            .add_attributes(attributes)
+            .set_data(answer)
+    )
+}
+
+/// Pays the USDT returned by the router to reward recipients in proportion
+/// to the ORAI each of them had
+pub fn reply_swap_rewards(deps: DepsMut, env: Env) -> Result<Response, ContractError> {
+    let pending = PENDING_REWARD_SWAP.load(deps.storage)?;
+    PENDING_REWARD_SWAP.remove(deps.storage);
+
+    let config = CONFIG_ITEM.load(deps.storage)?;
+    let usdt_contract = config.oraiswap_contract.usdt_contract;
+    let usdt_balance = utils::query_cw20_balance(
+        deps.as_ref(),
+        &usdt_contract,
+        env.contract.address.as_str()
+    )?;
+    let usdt_amount = usdt_balance.checked_sub(pending.usdt_balance).unwrap();
+
+    let swapped: u128 = pending.payouts
+        .iter()
+        .map(|(_, a)| a)
+        .sum();
+    let mut usdt_payouts: Vec<(String, u128)> = pending.payouts
+        .iter()
+        .map(|(address, orai)| {
+            let usdt = Uint128::from(usdt_amount).multiply_ratio(*orai, swapped).u128();
+            (address.clone(), usdt)
+        })
+        .collect();
+
+    let paid: u128 = usdt_payouts
+        .iter()
+        .map(|(_, a)| a)
+        .sum();
+    let largest = (0..usdt_payouts.len()).rev().max_by_key(|i| pending.payouts[*i].1);
+    if let Some(index) = largest {
+        usdt_payouts[index].1 = usdt_payouts[index].1.checked_add(usdt_amount - paid).unwrap();
+    }
+
+    let mut messages: Vec<CosmosMsg> = Vec::with_capacity(usdt_payouts.len());
+    let mut attributes: Vec<(String, String)> = Vec::with_capacity(usdt_payouts.len());
+    for (address, usdt) in usdt_payouts {
+        attributes.push((address.clone(), usdt.to_string()));
+        if usdt != 0 {
+            messages.push(utils::cw20_transfer_msg(&usdt_contract, &address, usdt)?);
+        }
+    }
+
+    let answer = to_json_binary(
+        &(ExecuteResponse::WithdrawRewards {
+            amount: Uint128::new(pending.amount),
+            usdt_amount: Uint128::new(usdt_amount),
+            status: ResponseStatus::Success,
+        })
+    )?;
+
+    Ok(
+        Response::new()
+            .add_messages(messages)
+            .add_attribute("action", "swapped rewards")
+            .add_attribute("usdt_amount", usdt_amount.to_string())
+            .add_attributes(attributes)
+            .set_data(answer)
+    )
+}
+
+/// ORAI that landed on the contract since `withdraw_rewards_to_contract`
+fn take_withdrawn_rewards(deps: DepsMut, env: &Env) -> StdResult<(u128, PendingRewards)> {
+    let pending = PENDING_REWARDS.load(deps.storage)?;
+    PENDING_REWARDS.remove(deps.storage);
+    let orai_balance = deps.querier.query_balance(&env.contract.address, ORAI)?;
+
+    let amount = orai_balance.amount.u128().saturating_sub(pending.orai_balance);
+
+    // Rewards paid out by staking messages since the last crank are swept along
+    let auto_withdrawn = AUTO_WITHDRAWN_REWARDS.may_load(deps.storage)?.unwrap_or_default();
+    AUTO_WITHDRAWN_REWARDS.remove(deps.storage);
+
+    Ok((amount.checked_add(auto_withdrawn).unwrap(), pending))
+}
+
+/// Records rewards withdrawn to the contract as a side effect of a staking message,
+/// which the SDK does on every delegate, undelegate and redelegate
+fn record_auto_withdrawn_rewards(storage: &mut dyn Storage, events: &[Event]) -> StdResult<()> {
+    if !REWARDS_TO_CONTRACT.may_load(storage)?.unwrap_or_default() {
+        return Ok(());
+    }
+
+    let mut amount: u128 = 0;
+    for event in events.iter().filter(|e| e.ty == "withdraw_rewards") {
+        let coins = event.attributes
+            .iter()
+            .filter(|a| a.key == "amount")
+            .flat_map(|a| a.value.split(','));
+        for coin in coins {
+            let split = coin.find(|c: char| !c.is_ascii_digit()).unwrap_or(coin.len());
+            let (value, denom) = coin.split_at(split);
+            if denom != ORAI {
+                continue;
+            }
+
+            let value = value.parse::<u128>().map_err(|_| {
+                StdError::generic_err(format!("Invalid rewards amount: {}", coin))
+            })?;
+            amount = amount.checked_add(value).unwrap();
+        }
+    }
+
+    if amount != 0 {
+        let recorded = AUTO_WITHDRAWN_REWARDS.may_load(storage)?.unwrap_or_default();
+        AUTO_WITHDRAWN_REWARDS.save(storage, &recorded.checked_add(amount).unwrap())?;
+    }
+
+    Ok(())
+}
+
+pub fn reply_staking_rewards(
+    deps: DepsMut,
+    _env: Env,
+    msg: Reply
+) -> Result<Response, ContractError> {
+    let response = msg.result.into_result().map_err(StdError::generic_err)?;
+    record_auto_withdrawn_rewards(deps.storage, &response.events)?;
+
+    Ok(Response::new())
+}
+
+/// Delegates `amount` into the stake pool without issuing shares, which
+/// raises the value of every share. With no shares out nobody could claim
+/// it, so the ORAI stays liquid instead
+fn compound_msgs(
+    storage: &mut dyn Storage,
+    config: &Config,
+    amount: u128
+) -> StdResult<Vec<SubMsg>> {
+    if amount == 0 {
+        return Ok(vec![]);
+    }
+
+    let mut pool = STAKE_POOL.may_load(storage)?.unwrap_or_default();
+    if pool.shares == 0 {
+        return Ok(vec![]);
+    }
+
+    pool.orai = pool.orai.checked_add(amount).unwrap();
+    STAKE_POOL.save(storage, &pool)?;
+
+    delegate_msgs(storage, config, amount)
+}
+
+/// Marks validators that left the active set inactive, brings back those
+/// that returned and redelegates any stake on inactive validators
+pub fn try_check_validators(mut deps: DepsMut, env: Env) -> Result<Response, ContractError> {
+    let mut config: Config = CONFIG_ITEM.load(deps.storage)?;
+    // Jailing usually comes with a slash, only move what is left
+    sync_delegations(deps.branch(), &env)?;
+
+    // The validator query also answers for jailed and unbonded validators,
+    // only the bonded set is returned by the list query
+    let bonded = deps.querier.query_all_validators()?;
+
+    let mut deactivated: Vec<String> = vec![];
+    let mut reactivated: Vec<String> = vec![];
+    for validator in config.validators.iter() {
+        let healthy = bonded.iter().any(|v| v.address == validator.address);
+        let inactive = config.inactive_validators.contains(&validator.address);
+        if !healthy && !inactive {
+            deactivated.push(validator.address.clone());
+        } else if healthy && inactive {
+            reactivated.push(validator.address.clone());
+        }
+    }
+
+    config.inactive_validators.retain(|v| !reactivated.contains(v));
+    config.inactive_validators.extend(deactivated.iter().cloned());
+    CONFIG_ITEM.save(deps.storage, &config)?;
+
+    let mut messages: Vec<SubMsg> = vec![];
+    let mut total_amount: u128 = 0;
+
+    // Stake stays where it is until at least one validator is active
+    if !config.active_validators().is_empty() {
+        for src_validator in config.inactive_validators.iter() {
+            let (redelegate_msgs, amount) = redelegate_msgs(deps.storage, &config, src_validator)?;
+            messages.extend(redelegate_msgs);
+            total_amount = total_amount.checked_add(amount).unwrap();
+        }
+    }
+
+    if deactivated.is_empty() && reactivated.is_empty() && messages.is_empty() {
+        return Err(ContractError::Std(StdError::generic_err("Validators are up to date")));
+    }
+
+    let answer = to_json_binary(
+        &(ExecuteResponse::Redelegate {
+            amount: Uint128::new(total_amount),
+            status: ResponseStatus::Success,
+        })
+    )?;
+
+    Ok(
+        Response::new()
+            .add_submessages(messages)
+            .add_attribute("action", "checked validators")
+            .add_attribute("deactivated", deactivated.join(","))
+            .add_attribute("reactivated", reactivated.join(","))
+            .set_data(answer)
+    )
+}
+
+/// Removes matured withdrawals of `user` among the first `limit` read,
+/// returning the ORAI and USDT claimed and the number of withdrawals read
+pub fn claim_matured_withdrawals(
+    storage: &mut dyn Storage,
+    user: &str,
+    current_time: u64,
+    limit: usize
+) -> StdResult<(u128, u128, usize)> {
+    let withdrawals = state::withdrawals();
+
+    // Only entries whose earliest claim time has passed are read
+    let max = Bound::exclusive((current_time.checked_add(1).unwrap(), (String::new(), 0u64)));
+    let matured = withdrawals.idx.user_claim_time
+        .sub_prefix(user.to_string())
+        .range(storage, None, Some(max), Order::Ascending)
+        .take(limit)
+        .collect::<StdResult<Vec<_>>>()?;
+
+    let scanned = matured.len();
+    let mut claim_amount = 0u128;
+    let mut usdt_claim_amount = 0u128;
+
+    for (key, withdrawal) in matured {
+        match withdrawal.claimable_at(storage)? {
+            Some(claim_time) if current_time >= claim_time => {}
+            _ => {
+                continue;
+            }
+        }
+
+        match withdrawal.token {
+            DepositToken::Orai => {
+                let payout = withdrawal.payout(storage)?;
+                claim_amount = claim_amount.checked_add(payout).unwrap();
+            }
+            DepositToken::Usdt => {
+                usdt_claim_amount = usdt_claim_amount.checked_add(withdrawal.amount).unwrap();
+            }
+        }
+        withdrawals.remove(storage, key)?;
+    }
+
+    Ok((claim_amount, usdt_claim_amount, scanned))
+}
+
+/// Loads the epoch collecting new withdrawals, starting the first one if needed
+pub fn load_current_epoch(storage: &dyn Storage, current_time: u64) -> StdResult<(u64, Epoch)> {
+    let epoch_id = CURRENT_EPOCH.may_load(storage)?.unwrap_or_default();
+    let epoch = EPOCHS.may_load(storage, epoch_id)?.unwrap_or(Epoch {
+        start_time: current_time,
+        ..Default::default()
+    });
+
+    Ok((epoch_id, epoch))
+}
+
+/// Reserves `amount` of buffered ORAI for a withdrawal. When instant withdrawals
+/// have drained the buffer, the rest is taken from refills already unbonding,
+/// then from the refill queued in `epoch`, so no other staker's stake is unbonded.
+/// Returns the amounts taken from scheduled refills and from the epoch.
+pub fn reserve_buffered(
+    storage: &mut dyn Storage,
+    current_time: u64,
+    amount: u128,
+    epoch: Option<&mut Epoch>
+) -> StdResult<(u128, u128)> {
+    let liquidity_buffer = load_liquidity_buffer(storage, current_time)?;
+    let from_buffer = liquidity_buffer.min(amount);
+    LIQUIDITY_BUFFER.save(storage, &(liquidity_buffer - from_buffer))?;
+
+    let mut remaining = amount - from_buffer;
+    let mut refilled: u128 = 0;
+    if remaining != 0 {
+        let refills = BUFFER_REFILLS.range(
+            storage,
+            None,
+            None,
+            Order::Ascending
+        ).collect::<StdResult<Vec<_>>>()?;
+
+        for (refill_time, refill) in refills {
+            if remaining == 0 {
+                break;
+            }
+
+            let taken = remaining.min(refill);
+            if taken == refill {
+                BUFFER_REFILLS.remove(storage, refill_time);
+            } else {
+                BUFFER_REFILLS.save(storage, refill_time, &(refill - taken))?;
+            }
+            refilled += taken;
+            remaining -= taken;
+        }
+    }
+
+    // The epoch's refill is unbonded for the withdrawal instead, the caller
+    // queues it with the withdrawal
+    let mut from_epoch: u128 = 0;
+    if let Some(epoch) = epoch.filter(|e| !e.processed && remaining != 0) {
+        from_epoch = remaining.min(epoch.refill_amount);
+        epoch.refill_amount -= from_epoch;
+        remaining -= from_epoch;
+    }
+
+    if remaining != 0 {
+        return Err(
+            StdError::generic_err("Buffered deposit is not covered by the liquidity buffer")
+        );
+    }
+
+    Ok((refilled, from_epoch))
+}
+
+/// Splits up to the sum of `amounts` in proportion to them, rounding
+/// remainders going to the largest amounts first
+fn split_pro_rata(amounts: &[u128], amount: u128) -> Vec<u128> {
+    let total: u128 = amounts.iter().sum();
+    let amount = amount.min(total);
+
+    let mut taken: Vec<u128> = amounts
+        .iter()
+        .map(|a| Uint128::from(*a).multiply_ratio(amount, total.max(1)).u128())
+        .collect();
+    let mut remainder = amount - taken.iter().sum::<u128>();
+
+    let mut by_size: Vec<usize> = (0..amounts.len()).collect();
+    by_size.sort_by_key(|i| std::cmp::Reverse(amounts[*i]));
+    for i in by_size {
+        if remainder == 0 {
+            break;
+        }
+
+        let extra = remainder.min(amounts[i] - taken[i]);
+        taken[i] += extra;
+        remainder -= extra;
+    }
+
+    taken
+}
+
+/// Moves matured buffer refills into the liquidity buffer and returns its size
+pub fn load_liquidity_buffer(storage: &mut dyn Storage, current_time: u64) -> StdResult<u128> {
+    let mut liquidity_buffer = LIQUIDITY_BUFFER.may_load(storage)?.unwrap_or_default();
+
+    let matured = BUFFER_REFILLS.range(
+        storage,
+        None,
+        Some(Bound::inclusive(current_time)),
+        Order::Ascending
+    ).collect::<StdResult<Vec<_>>>()?;
+
+    for (refill_time, amount) in matured {
+        liquidity_buffer = liquidity_buffer.checked_add(amount).unwrap();
+        BUFFER_REFILLS.remove(storage, refill_time);
+    }
+
+    Ok(liquidity_buffer)
+}
+
+/// Checks that validators exist, are listed once and that weights add up to `MAX_BPS`
+pub fn validate_validators(
+    deps: Deps,
+    validators: &[ValidatorWithWeight]
+) -> Result<(), ContractError> {
+    if validators.is_empty() {
+        return Err(ContractError::NoValidators {});
+    }
+
+    let chain_validators = deps.querier.query_all_validators()?;
+    let mut seen: Vec<&str> = Vec::with_capacity(validators.len());
+    let mut total: u128 = 0;
+
+    for validator in validators {
+        if validator.weight == 0 {
+            return Err(ContractError::ZeroValidatorWeight {
+                validator: validator.address.clone(),
+            });
+        }
+
+        if seen.contains(&validator.address.as_str()) {
+            return Err(ContractError::DuplicateValidator {
+                validator: validator.address.clone(),
+            });
+        }
+        seen.push(&validator.address);
+
+        let exists = chain_validators.iter().any(|v| v.address == validator.address);
+        if !exists {
+            return Err(ContractError::UnknownValidator {
+                validator: validator.address.clone(),
+            });
+        }
+
+        total = total.saturating_add(validator.weight);
+    }
+
+    if total != (MAX_BPS as u128) {
+        return Err(ContractError::InvalidValidatorWeights { total });
+    }
+
+    Ok(())
+}
+
+/// Delegates deposits left liquid by failed delegations and undelegates
+/// what failed to unbond with its epoch. Stake that failed to redelegate is
+/// unbonded and delegated again once liquid.
+pub fn try_retry_staking(deps: DepsMut, env: Env) -> Result<Response, ContractError> {
+    let config = CONFIG_ITEM.load(deps.storage)?;
+    let current_time = env.block.time.seconds();
+
+    let mut messages: Vec<SubMsg> = vec![];
+
+    let mut delegated = PENDING_DELEGATION.may_load(deps.storage)?.unwrap_or_default();
+
+    let unbonded = UNBONDING_REDELEGATIONS.range(
+        deps.storage,
+        None,
+        Some(Bound::inclusive(current_time)),
+        Order::Ascending
+    ).collect::<StdResult<Vec<_>>>()?;
+    for (completion_time, amount) in unbonded {
+        UNBONDING_REDELEGATIONS.remove(deps.storage, completion_time);
+        delegated = delegated.checked_add(amount).unwrap();
+    }
+
+    // After a shutdown liquid ORAI is owed to withdrawals and is not delegated again
+    if delegated != 0 && !config.shutdown {
+        PENDING_DELEGATION.save(deps.storage, &0)?;
+        messages.extend(delegate_msgs(deps.storage, &config, delegated)?);
+    }
+
+    let failed_redelegations = FAILED_REDELEGATIONS.range(
+        deps.storage,
+        None,
+        None,
+        Order::Ascending
+    ).collect::<StdResult<Vec<_>>>()?;
+
+    let mut undelegated: u128 = 0;
+    let mut settled = false;
+    for (validator, amount) in failed_redelegations {
+        FAILED_REDELEGATIONS.remove(deps.storage, validator.clone());
+        settled = true;
+
+        // Stake on a validator that is active again stays where it is
+        let active = config.active_validators().iter().any(|v| v.address == validator);
+        let tracked = VALIDATOR_DELEGATIONS.may_load(deps.storage, validator.clone())?;
+        let tracked = tracked.unwrap_or_default();
+        let amount = amount.min(tracked);
+        if active || amount == 0 {
+            continue;
+        }
+
+        VALIDATOR_DELEGATIONS.save(deps.storage, validator.clone(), &(tracked - amount))?;
+        let op = StakingOp {
+            action: StakingAction::Unbond,
+            validator,
+            amount,
+            epoch: None,
+        };
+        messages.push(staking_submsg(deps.storage, op)?);
+        undelegated = undelegated.checked_add(amount).unwrap();
+    }
+
+    let failed_undelegations = FAILED_UNDELEGATIONS.range(
+        deps.storage,
+        None,
+        None,
+        Order::Ascending
+    ).collect::<StdResult<Vec<_>>>()?;
+
+    for (epoch_id, amount) in failed_undelegations {
+        FAILED_UNDELEGATIONS.remove(deps.storage, epoch_id);
+        let (undelegate_msgs, undelegations) = undelegate_msgs(
+            deps.storage,
+            &config,
+            amount,
+            epoch_id
+        )?;
+        messages.extend(undelegate_msgs);
+
+        // The epoch can only be claimed once the retried part has unbonded too
+        let mut epoch = EPOCHS.load(deps.storage, epoch_id)?;
+        epoch.claim_time = epoch.claim_time.max(
+            current_time.checked_add(config.unbonding_period).unwrap()
+        );
+        epoch.undelegations.extend(
+            undelegations.into_iter().map(|u| Undelegation {
+                creation_height: Some(env.block.height),
+                ..u
+            })
+        );
+        EPOCHS.save(deps.storage, epoch_id, &epoch)?;
+
+        undelegated = undelegated.checked_add(amount).unwrap();
+    }
+
+    if messages.is_empty() && !settled {
+        return Err(ContractError::Std(StdError::generic_err("Nothing to retry")));
+    }
+
+    let answer = to_json_binary(
+        &(ExecuteResponse::RetryStaking {
+            delegated: Uint128::new(delegated),
+            undelegated: Uint128::new(undelegated),
+            status: ResponseStatus::Success,
+        })
+    )?;
+
+    Ok(Response::new().add_submessages(messages).set_data(answer))
+}
+
+/// Undoes the bookkeeping of a failed staking message so the crank can retry it
+pub fn reply_staking(deps: DepsMut, env: Env, msg: Reply) -> Result<Response, ContractError> {
+    let op_id = msg.id.checked_sub(STAKING_REPLY_ID_START).unwrap();
+    let op = STAKING_OPS.load(deps.storage, op_id)?;
+    STAKING_OPS.remove(deps.storage, op_id);
+
+    let error = match msg.result {
+        SubMsgResult::Ok(response) => {
+            record_auto_withdrawn_rewards(deps.storage, &response.events)?;
+
+            if op.action == StakingAction::Unbond {
+                let config = CONFIG_ITEM.load(deps.storage)?;
+                let completion_time = env.block.time
+                    .seconds()
+                    .checked_add(config.unbonding_period)
+                    .unwrap();
+                UNBONDING_REDELEGATIONS.update(
+                    deps.storage,
+                    completion_time,
+                    |unbonding| -> StdResult<_> {
+                        Ok(unbonding.unwrap_or_default().checked_add(op.amount).unwrap())
+                    }
+                )?;
+            }
+            return Ok(Response::new());
+        }
+        SubMsgResult::Err(error) => error,
+    };
+
+    match op.action.clone() {
+        StakingAction::Delegate => {
+            VALIDATOR_DELEGATIONS.update(
+                deps.storage,
+                op.validator.clone(),
+                |delegated| -> StdResult<_> {
+                    Ok(delegated.unwrap_or_default().saturating_sub(op.amount))
+                }
+            )?;
+
+            // The deposit stays liquid in the contract until it is delegated again
+            let pending = PENDING_DELEGATION.may_load(deps.storage)?.unwrap_or_default();
+            PENDING_DELEGATION.save(deps.storage, &pending.checked_add(op.amount).unwrap())?;
+        }
+        StakingAction::Undelegate => {
+            VALIDATOR_DELEGATIONS.update(
+                deps.storage,
+                op.validator.clone(),
+                |delegated| -> StdResult<_> {
+                    Ok(delegated.unwrap_or_default().checked_add(op.amount).unwrap())
+                }
+            )?;
+
+            let epoch_id = op.epoch.unwrap();
+            let mut epoch = EPOCHS.load(deps.storage, epoch_id)?;
+            let mut remaining = op.amount;
+            for undelegation in epoch.undelegations.iter_mut().rev() {
+                if undelegation.validator == op.validator && remaining != 0 {
+                    let amount = remaining.min(undelegation.amount);
+                    undelegation.amount -= amount;
+                    remaining -= amount;
+                }
+            }
+            epoch.undelegations.retain(|u| u.amount != 0);
+            EPOCHS.save(deps.storage, epoch_id, &epoch)?;
+
+            FAILED_UNDELEGATIONS.update(
+                deps.storage,
+                epoch_id,
+                |failed| -> StdResult<_> {
+                    Ok(failed.unwrap_or_default().checked_add(op.amount).unwrap())
+                }
+            )?;
+        }
+        StakingAction::Redelegate { src_validator } => {
+            VALIDATOR_DELEGATIONS.update(
+                deps.storage,
+                op.validator.clone(),
+                |delegated| -> StdResult<_> {
+                    Ok(delegated.unwrap_or_default().saturating_sub(op.amount))
+                }
+            )?;
+            record_failed_redelegation(deps.storage, &src_validator, op.amount)?;
+        }
+        StakingAction::Unbond => {
+            record_failed_redelegation(deps.storage, &op.validator, op.amount)?;
+        }
+    }
+
+    Ok(
+        Response::new()
+            .add_attribute("action", "staking failed")
+            .add_attribute("validator", op.validator)
+            .add_attribute("amount", op.amount.to_string())
+            .add_attribute("error", error)
+    )
+}
+
+/// Puts stake that could not be moved back on `validator`, for `RetryStaking`
+fn record_failed_redelegation(
+    storage: &mut dyn Storage,
+    validator: &str,
+    amount: u128
+) -> StdResult<()> {
+    VALIDATOR_DELEGATIONS.update(storage, validator.to_string(), |delegated| -> StdResult<_> {
+        Ok(delegated.unwrap_or_default().checked_add(amount).unwrap())
+    })?;
+    FAILED_REDELEGATIONS.update(storage, validator.to_string(), |failed| -> StdResult<_> {
+        Ok(failed.unwrap_or_default().checked_add(amount).unwrap())
+    })?;
+
+    Ok(())
+}
+
+/// Wraps a staking message so that its failure is recorded instead of reverting the call
+fn staking_submsg(storage: &mut dyn Storage, op: StakingOp) -> StdResult<SubMsg> {
+    let op_id = STAKING_OP_ID.may_load(storage)?.unwrap_or_default();
+    STAKING_OP_ID.save(storage, &op_id.checked_add(1).unwrap())?;
+
+    let amount = coin(op.amount, ORAI);
+    let staking_msg = match op.action {
+        StakingAction::Delegate => StakingMsg::Delegate {
+            validator: op.validator.clone(),
+            amount,
+        },
+        StakingAction::Undelegate | StakingAction::Unbond => StakingMsg::Undelegate {
+            validator: op.validator.clone(),
+            amount,
+        },
+        StakingAction::Redelegate { ref src_validator } => StakingMsg::Redelegate {
+            src_validator: src_validator.clone(),
+            dst_validator: op.validator.clone(),
+            amount,
+        },
+    };
+    STAKING_OPS.save(storage, op_id, &op)?;
+
+    Ok(
+        SubMsg::reply_always(
+            CosmosMsg::Staking(staking_msg),
+            STAKING_REPLY_ID_START.checked_add(op_id).unwrap()
+        )
+    )
+}
+
+/// Moves all stake tracked on `src_validator` to the active validators by
+/// weight, returning the messages and the amount moved
+fn redelegate_msgs(
+    storage: &mut dyn Storage,
+    config: &Config,
+    src_validator: &str
+) -> StdResult<(Vec<SubMsg>, u128)> {
+    let amount = VALIDATOR_DELEGATIONS.may_load(storage, src_validator.to_string())?;
+    let amount = amount.unwrap_or_default();
+    let split = config.split_by_weight(amount);
+    if amount == 0 || split.is_empty() {
+        return Ok((vec![], 0));
+    }
+
+    VALIDATOR_DELEGATIONS.save(storage, src_validator.to_string(), &0)?;
+
+    let mut messages: Vec<SubMsg> = Vec::with_capacity(split.len());
+    for (dst_validator, individual_amount) in split {
+        if individual_amount == 0 {
+            continue;
+        }
+
+        VALIDATOR_DELEGATIONS.update(
+            storage,
+            dst_validator.clone(),
+            |delegated| -> StdResult<_> {
+                Ok(delegated.unwrap_or_default().checked_add(individual_amount).unwrap())
+            }
+        )?;
+
+        // A validator still receiving a redelegation cannot pass it on, the
+        // reply records the failure for `RetryStaking` to unbond instead
+        let op = StakingOp {
+            action: StakingAction::Redelegate {
+                src_validator: src_validator.to_string(),
+            },
+            validator: dst_validator,
+            amount: individual_amount,
+            epoch: None,
+        };
+        messages.push(staking_submsg(storage, op)?);
+    }
+
+    Ok((messages, amount))
+}
+
+/// Reads the contract's actual delegations and books any slashing loss
+/// against the stake pool, returning the loss
+pub fn sync_delegations(deps: DepsMut, env: &Env) -> StdResult<u128> {
+    let tracked = VALIDATOR_DELEGATIONS.range(
+        deps.storage,
+        None,
+        None,
+        Order::Ascending
+    ).collect::<StdResult<Vec<_>>>()?;
+
+    let mut loss: u128 = 0;
+    for (validator, delegated) in tracked {
+        if delegated == 0 {
+            continue;
+        }
+
+        let actual = deps.querier
+            .query_delegation(&env.contract.address, &validator)?
+            .map(|d| d.amount.amount.u128())
+            .unwrap_or_default();
+        if actual < delegated {
+            loss = loss.checked_add(delegated - actual).unwrap();
+            VALIDATOR_DELEGATIONS.save(deps.storage, validator, &actual)?;
+        }
+    }
+
+    if loss != 0 {
+        book_loss(deps.storage, env.block.time.seconds(), loss)?;
+    }
+
+    Ok(loss)
+}
+
+/// Shares a slashing loss pro rata between everything that is still delegated:
+/// the pool's stake, ORAI queued in the current epoch and undelegations waiting
+/// for a retry. Buffered deposits are owed in full, so the loss on ORAI
+/// unbonding to refill the buffer is charged to the stake pool.
+fn book_loss(storage: &mut dyn Storage, current_time: u64, loss: u128) -> StdResult<()> {
+    let config = CONFIG_ITEM.load(storage)?;
+    let mut pool = STAKE_POOL.may_load(storage)?.unwrap_or_default();
+    let (epoch_id, mut epoch) = load_current_epoch(storage, current_time)?;
+
+    // After a shutdown the pool is no longer delegated, and ORAI that failed
+    // to delegate is liquid either way
+    let pending = PENDING_DELEGATION.may_load(storage)?.unwrap_or_default();
+    let unbonding = UNBONDING_REDELEGATIONS.range(storage, None, None, Order::Ascending)
+        .map(|item| item.map(|(_, amount)| amount))
+        .sum::<StdResult<u128>>()?;
+    let staked = match config.shutdown {
+        true => 0,
+        false =>
+            Uint128::new(pool.orai)
+                .checked_sub(Uint128::new(pending))?
+                .checked_sub(Uint128::new(unbonding))?
+                .u128(),
+    };
+
+    let failed = FAILED_UNDELEGATIONS.range(
+        storage,
+        None,
+        None,
+        Order::Ascending
+    ).collect::<StdResult<Vec<_>>>()?;
+
+    // Each holder is exposed to its share of the stake but can only be
+    // charged what it owns, the stake pool also carrying the refills
+    let mut exposed = vec![
+        staked.checked_add(epoch.refill_amount).unwrap(),
+        epoch.undelegate_amount
+    ];
+    let mut owned = vec![staked, epoch.undelegate_amount];
+    let mut refills = vec![epoch.refill_amount];
+    for (failed_epoch_id, amount) in failed.iter() {
+        // A retried undelegation also carries its epoch's refill, which
+        // after a shutdown is charged to the epoch's withdrawals instead
+        let failed_epoch = EPOCHS.load(storage, *failed_epoch_id)?;
+        let withdrawn = (*amount).min(failed_epoch.undelegate_amount);
+        let refill = amount - withdrawn;
+        match config.shutdown {
+            true => exposed.push(*amount),
+            false => {
+                exposed[0] = exposed[0].checked_add(refill).unwrap();
+                exposed.push(withdrawn);
+            }
+        }
+        owned.push(withdrawn);
+        refills.push(refill);
+    }
+
+    let total: u128 = exposed.iter().sum();
+    if loss > total {
+        return Err(StdError::generic_err("Slashing loss exceeds the tracked stake"));
+    }
+
+    let mut losses = split_pro_rata(&exposed, loss);
+    let mut excess: u128 = 0;
+    for (loss, owned) in losses.iter_mut().zip(owned.iter()) {
+        excess += loss.saturating_sub(*owned);
+        *loss = (*loss).min(*owned);
+    }
+
+    // What a holder cannot cover moves to those that still can
+    let headroom: Vec<u128> = owned
+        .iter()
+        .zip(losses.iter())
+        .map(|(owned, loss)| owned - loss)
+        .collect();
+    for (loss, extra) in losses.iter_mut().zip(split_pro_rata(&headroom, excess)) {
+        *loss += extra;
+        excess -= extra;
+    }
+
+    // Only when nobody is left to charge do the refills take the rest
+    let refill_losses = split_pro_rata(&refills, excess);
+
+    pool.orai -= losses[0];
+    STAKE_POOL.save(storage, &pool)?;
+
+    if losses[1] != 0 || refill_losses[0] != 0 {
+        epoch.book_undelegation_loss(losses[1])?;
+        epoch.refill_amount -= refill_losses[0];
+        EPOCHS.save(storage, epoch_id, &epoch)?;
+    }
+
+    let mut refill_loss: u128 = 0;
+    for (i, (failed_epoch_id, amount)) in failed.into_iter().enumerate() {
+        let (loss, lost_refill) = (losses[i + 2], refill_losses[i + 1]);
+        if loss == 0 && lost_refill == 0 {
+            continue;
+        }
+
+        FAILED_UNDELEGATIONS.save(storage, failed_epoch_id, &(amount - loss - lost_refill))?;
+
+        let mut failed_epoch = EPOCHS.load(storage, failed_epoch_id)?;
+        failed_epoch.book_undelegation_loss(loss)?;
+        EPOCHS.save(storage, failed_epoch_id, &failed_epoch)?;
+        refill_loss += lost_refill;
+    }
+
+    // Refills of processed epochs are scheduled already or have matured
+    // into the buffer, which are taken from as far as they go
+    let refills = BUFFER_REFILLS.range(
+        storage,
+        None,
+        None,
+        Order::Descending
+    ).collect::<StdResult<Vec<_>>>()?;
+    for (refill_time, refill) in refills {
+        if refill_loss == 0 {
+            break;
+        }
+
+        let taken = refill_loss.min(refill);
+        BUFFER_REFILLS.save(storage, refill_time, &(refill - taken))?;
+        refill_loss -= taken;
+    }
+
+    if refill_loss != 0 {
+        let buffer = LIQUIDITY_BUFFER.may_load(storage)?.unwrap_or_default();
+        LIQUIDITY_BUFFER.save(storage, &buffer.saturating_sub(refill_loss))?;
+    }
+
+    Ok(())
+}
+
+/// Delegates `amount` split by weight and records it per validator
+pub fn delegate_msgs(
+    storage: &mut dyn Storage,
+    config: &Config,
+    amount: u128
+) -> StdResult<Vec<SubMsg>> {
+    if config.shutdown && amount != 0 {
+        return Err(StdError::generic_err("Contract is shut down"));
+    }
+
+    let split = config.split_by_weight(amount);
+    if split.is_empty() && amount != 0 {
+        return Err(StdError::generic_err("No active validators to delegate to"));
+    }
+
+    let mut messages: Vec<SubMsg> = Vec::with_capacity(split.len());
+
+    for (validator, individual_amount) in split {
+        if individual_amount == 0 {
+            continue;
+        }
+
+        VALIDATOR_DELEGATIONS.update(
+            storage,
+            validator.clone(),
+            |delegated| -> StdResult<_> {
+                Ok(delegated.unwrap_or_default().checked_add(individual_amount).unwrap())
+            }
+        )?;
+
+        let op = StakingOp {
+            action: StakingAction::Delegate,
+            validator,
+            amount: individual_amount,
+            epoch: None,
+        };
+        messages.push(staking_submsg(storage, op)?);
+    }
+
+    Ok(messages)
+}
+
+/// Undelegates exactly `amount`, split by weight but never more than is
+/// delegated to a validator, returning the messages and the amounts sent
+pub fn undelegate_msgs(
+    storage: &mut dyn Storage,
+    config: &Config,
+    amount: u128,
+    epoch_id: u64
+) -> StdResult<(Vec<SubMsg>, Vec<Undelegation>)> {
+    let mut delegated: BTreeMap<String, u128> = VALIDATOR_DELEGATIONS.range(
+        storage,
+        None,
+        None,
+        Order::Ascending
+    ).collect::<StdResult<_>>()?;
+    let mut undelegated: BTreeMap<String, u128> = BTreeMap::new();
+    let mut remaining = amount;
+
+    for (validator, individual_amount) in config.split_by_weight(amount) {
+        let available = delegated.entry(validator.clone()).or_default();
+        let taken = individual_amount.min(*available);
+        *available -= taken;
+        *undelegated.entry(validator).or_default() += taken;
+        remaining -= taken;
+    }
+
+    // Whatever a validator could not cover comes from any stake left elsewhere
+    for (validator, available) in delegated.iter_mut() {
+        if remaining == 0 {
+            break;
+        }
+
+        let taken = remaining.min(*available);
+        *available -= taken;
+        *undelegated.entry(validator.clone()).or_default() += taken;
+        remaining -= taken;
+    }
+
+    if remaining != 0 {
+        return Err(StdError::generic_err("Not enough delegated ORAI to undelegate"));
+    }
+
+    let mut messages: Vec<SubMsg> = Vec::with_capacity(undelegated.len());
+    let mut undelegations: Vec<Undelegation> = Vec::with_capacity(undelegated.len());
+
+    for (validator, individual_amount) in undelegated {
+        if individual_amount == 0 {
+            continue;
+        }
+
+        VALIDATOR_DELEGATIONS.save(storage, validator.clone(), &delegated[&validator])?;
+
+        let op = StakingOp {
+            action: StakingAction::Undelegate,
+            validator: validator.clone(),
+            amount: individual_amount,
+            epoch: Some(epoch_id),
+        };
+        messages.push(staking_submsg(storage, op)?);
+        undelegations.push(Undelegation {
+            validator,
+            amount: individual_amount,
+            creation_height: None,
+        });
+    }
+
+    Ok((messages, undelegations))
+}
+
+/// Takes `amount` out of `entries` in proportion to their size, the
+/// rounding remainder coming from the largest entries
+fn take_pro_rata(entries: &mut [Undelegation], amount: u128) -> Vec<Undelegation> {
+    let amounts: Vec<u128> = entries
+        .iter()
+        .map(|e| e.amount)
+        .collect();
+    let taken = split_pro_rata(&amounts, amount);
+
+    entries
+        .iter_mut()
+        .zip(taken)
+        .filter(|(_, amount)| *amount != 0)
+        .map(|(entry, amount)| {
+            entry.amount -= amount;
+            Undelegation {
+                validator: entry.validator.clone(),
+                amount,
+                creation_height: entry.creation_height,
+            }
+        })
+        .collect()
+}
+
+fn query_config(deps: Deps) -> StdResult<QueryResponse> {
+    let config = CONFIG_ITEM.load(deps.storage)?;
+    config.to_answer()
+}
+
+pub fn query_user_info(deps: Deps, address: String) -> StdResult<QueryResponse> {
+    let config = CONFIG_ITEM.load(deps.storage)?;
+    // Get Tier from staking amount
+    let staked_amount = get_staked_amount(deps, &address);
+    let old_user_info = USER_INFOS.may_load(deps.storage, address.clone())?.unwrap_or(
+        state::UserInfo {
+            ..Default::default()
+        }
+    );
+
+    let total_usd_deposit = old_user_info.usd_deposit
+        .checked_add(staked_amount.staked_usd_amount)
+        .unwrap();
+    let tier = config.tier_by_deposit(total_usd_deposit);
+
+    let user_info = USER_INFOS.may_load(deps.storage, address)?.unwrap_or(state::UserInfo {
+        tier,
+        ..Default::default()
+    });
+
+    let pool = STAKE_POOL.may_load(deps.storage)?.unwrap_or_default();
+    let answer = user_info.to_answer(&pool);
+    Ok(answer)
+}
+
+pub fn query_withdrawals(
+    deps: Deps,
+    env: Env,
+    address: String,
+    status: Option<WithdrawalStatus>,
+    start_after: Option<u64>,
+    limit: Option<u32>
+) -> StdResult<QueryResponse> {
+    let limit = limit.unwrap_or(50) as usize;
+    let min = start_after.map(Bound::exclusive);
+    let current_time = env.block.time.seconds();
+    let withdrawals = state::withdrawals();
+
+    let mut serialized_withdrawals: Vec<SerializedWithdrawals> = Vec::new();
+    for item in withdrawals
+        .prefix(address.clone())
+        .range(deps.storage, min, None, Order::Ascending) {
+        if serialized_withdrawals.len() >= limit {
+            break;
+        }
+
+        let (id, withdrawal) = item?;
+        let matured = withdrawal
+            .claimable_at(deps.storage)?
+            .is_some_and(|claim_time| current_time >= claim_time);
+
+        let selected = match status {
+            None => true,
+            Some(WithdrawalStatus::Pending) => !matured,
+            Some(WithdrawalStatus::Matured) => matured,
+        };
+        if selected {
+            serialized_withdrawals.push(withdrawal.to_serialized(deps.storage, id)?);
+        }
+    }
+
+    // Totals cover every matured withdrawal of the user, not only this page
+    let mut total_claimable = 0u128;
+    let mut total_usdt_claimable = 0u128;
+    let max = Bound::exclusive((current_time.checked_add(1).unwrap(), (String::new(), 0u64)));
+    for item in withdrawals.idx.user_claim_time
+        .sub_prefix(address)
+        .range(deps.storage, None, Some(max), Order::Ascending) {
+        let (_, withdrawal) = item?;
+        match withdrawal.claimable_at(deps.storage)? {
+            Some(claim_time) if current_time >= claim_time => {}
+            _ => {
+                continue;
+            }
+        }
+
+        match withdrawal.token {
+            DepositToken::Orai => {
+                let payout = withdrawal.payout(deps.storage)?;
+                total_claimable = total_claimable.checked_add(payout).unwrap();
+            }
+            DepositToken::Usdt => {
+                total_usdt_claimable = total_usdt_claimable
+                    .checked_add(withdrawal.amount)
+                    .unwrap();
+            }
+        }
+    }
+
+    let answer = QueryResponse::Withdrawals {
+        withdrawals: serialized_withdrawals,
+        total_claimable: Uint128::new(total_claimable),
+        total_usdt_claimable: Uint128::new(total_usdt_claimable),
+    };
+
+    Ok(answer)
+}
+
+pub fn query_unbonding_schedule(
+    deps: Deps,
+    from: u64,
+    to: u64,
+    bucket_seconds: u64
+) -> StdResult<QueryResponse> {
+    if bucket_seconds == 0 || from >= to {
+        return Err(StdError::generic_err("Specify a non-empty range and bucket size"));
+    }
+
+    // Stored claim times never exceed the epoch claim times, so entries
+    // claimable before "to" are all stored before it
+    let max = PrefixBound::exclusive(to);
+    let mut buckets: BTreeMap<u64, u128> = BTreeMap::new();
+    let mut total = 0u128;
+
+    let withdrawals = state::withdrawals();
+    for item in withdrawals.idx.claim_time.prefix_range_raw(
+        deps.storage,
+        None,
+        Some(max),
+        Order::Ascending
+    ) {
+        let (_, withdrawal) = item?;
+        if withdrawal.token != DepositToken::Orai {
+            continue;
+        }
+
+        let claim_time = withdrawal.claimable_at(deps.storage)?.unwrap_or(withdrawal.claim_time);
+        if claim_time < from || claim_time >= to {
+            continue;
+        }
+
+        let bucket = from + ((claim_time - from) / bucket_seconds) * bucket_seconds;
+        let payout = withdrawal.payout(deps.storage)?;
+        let amount = buckets.entry(bucket).or_default();
+        *amount = amount.checked_add(payout).unwrap();
+        total = total.checked_add(payout).unwrap();
+    }
+
+    let answer = QueryResponse::UnbondingSchedule {
+        buckets: buckets
+            .into_iter()
+            .map(|(start_time, amount)| UnbondingBucket {
+                start_time,
+                amount: Uint128::new(amount),
+            })
+            .collect(),
+        total: Uint128::new(total),
+    };
+
+    Ok(answer)
+}
+
+pub fn query_current_epoch(deps: Deps, env: Env) -> StdResult<QueryResponse> {
+    let config = CONFIG_ITEM.load(deps.storage)?;
+    let (epoch_id, epoch) = load_current_epoch(deps.storage, env.block.time.seconds())?;
+
+    Ok(epoch.to_answer(epoch_id, config.epoch_period))
+}
+
+/// Compares the ORAI owed to users with what the contract holds, delegated,
+/// unbonding or liquid
+pub fn query_solvency(deps: Deps, env: Env) -> StdResult<QueryResponse> {
+    let config = CONFIG_ITEM.load(deps.storage)?;
+    let current_time = env.block.time.seconds();
+
+    let pool = STAKE_POOL.may_load(deps.storage)?.unwrap_or_default();
+    let mut user_deposits: u128 = 0;
+    for item in USER_INFOS.range(deps.storage, None, None, Order::Ascending) {
+        let (_, user_info) = item?;
+        let owed = user_info.orai_buffered.checked_add(pool.orai_for(user_info.shares)).unwrap();
+        user_deposits = user_deposits.checked_add(owed).unwrap();
+    }
+
+    let mut pending_withdrawals: u128 = 0;
+    for item in state::withdrawals().range(deps.storage, None, None, Order::Ascending) {
+        let (_, withdrawal) = item?;
+        if withdrawal.token == DepositToken::Orai {
+            let payout = withdrawal.payout(deps.storage)?;
+            pending_withdrawals = pending_withdrawals.checked_add(payout).unwrap();
+        }
+    }
+
+    // Validators dropped from the config can still hold tracked stake
+    let mut addresses: Vec<String> = config.validators
+        .iter()
+        .map(|v| v.address.clone())
+        .collect();
+    for address in VALIDATOR_DELEGATIONS.keys(deps.storage, None, None, Order::Ascending) {
+        let address = address?;
+        if !addresses.contains(&address) {
+            addresses.push(address);
+        }
+    }
+
+    let mut delegated: u128 = 0;
+    let mut validators: Vec<ValidatorSolvency> = Vec::with_capacity(addresses.len());
+    for address in addresses {
+        let tracked = VALIDATOR_DELEGATIONS.may_load(deps.storage, address.clone())?;
+        let actual = utils::query_delegation(deps, &env, &address)?
+            .map(|delegation| delegation.amount.amount.u128())
+            .unwrap_or_default();
+        delegated = delegated.checked_add(actual).unwrap();
+
+        validators.push(ValidatorSolvency {
+            validator: address,
+            tracked: Uint128::new(tracked.unwrap_or_default()),
+            actual: Uint128::new(actual),
+        });
+    }
+
+    let mut unbonding: u128 = UNBONDING_REDELEGATIONS.range(
+        deps.storage,
+        Some(Bound::exclusive(current_time)),
+        None,
+        Order::Ascending
+    )
+        .map(|item| item.map(|(_, amount)| amount))
+        .sum::<StdResult<u128>>()?;
+
+    // Retries and the shutdown push an older epoch's claim time past later
+    // ones, so every epoch is checked
+    for item in EPOCHS.range(deps.storage, None, None, Order::Ascending) {
+        let (_, epoch) = item?;
+        if !epoch.processed || epoch.claim_time <= current_time {
+            continue;
+        }
+
+        for undelegation in epoch.undelegations.iter() {
+            unbonding = unbonding.checked_add(undelegation.amount).unwrap();
+        }
+    }
+
+    let balance = deps.querier.query_balance(&env.contract.address, ORAI)?.amount.u128();
+
+    let liabilities = user_deposits.checked_add(pending_withdrawals).unwrap();
+    let assets = delegated
+        .checked_add(unbonding)
+        .and_then(|v| v.checked_add(balance))
+        .unwrap();
+
+    let answer = QueryResponse::Solvency {
+        user_deposits: Uint128::new(user_deposits),
+        pending_withdrawals: Uint128::new(pending_withdrawals),
+        delegated: Uint128::new(delegated),
+        unbonding: Uint128::new(unbonding),
+        balance: Uint128::new(balance),
+        surplus: Uint128::new(assets.saturating_sub(liabilities)),
+        shortfall: Uint128::new(liabilities.saturating_sub(assets)),
+        validators,
+    };
+
+    Ok(answer)
+}
+
+pub fn query_pending_changes(deps: Deps) -> StdResult<QueryResponse> {
+    let changes = PENDING_CHANGES.range(deps.storage, None, None, Order::Ascending)
+        .map(|item| item.map(|(id, pending)| pending.to_serialized(id)))
+        .collect::<StdResult<Vec<_>>>()?;
+
+    Ok(QueryResponse::PendingChanges { changes })
+}
+
+pub fn query_roles(deps: Deps) -> StdResult<QueryResponse> {
+    let config = CONFIG_ITEM.load(deps.storage)?;
+
+    let owner: Vec<String> = if config.admin.is_empty() { vec![] } else { vec![config.admin] };
+    let mut roles = vec![RoleMembers {
+        role: Role::Owner,
+        members: owner,
+    }];
+
+    for role in Role::GRANTABLE {
+        let members = ROLE_MEMBERS.prefix(role.key().to_string())
+            .keys(deps.storage, None, None, Order::Ascending)
+            .collect::<StdResult<Vec<_>>>()?;
+        roles.push(RoleMembers { role, members });
+    }
+
+    Ok(QueryResponse::Roles { roles })
+}
+
+/// Proposed admin, if the proposal has not expired yet
+pub fn query_pending_admin(deps: Deps, env: Env) -> StdResult<QueryResponse> {
+    let pending = PENDING_ADMIN.may_load(deps.storage)?.filter(|pending| {
+        env.block.time.seconds() < pending.expires_at
+    });
+
+    Ok(QueryResponse::PendingAdmin {
+        admin: pending.as_ref().map(|p| p.address.clone()),
+        expires_at: pending.map(|p| p.expires_at),
+    })
+}
+
+/// Lists the contract's delegation, pending ORAI rewards and redelegatable
+/// amount for every configured validator
+pub fn query_delegations(deps: Deps, env: Env) -> StdResult<QueryResponse> {
+    let config = CONFIG_ITEM.load(deps.storage)?;
+
+    let mut delegations: Vec<ValidatorDelegation> = Vec::with_capacity(config.validators.len());
+    let mut total_delegated: u128 = 0;
+    let mut total_rewards: u128 = 0;
+    let mut total_can_redelegate: u128 = 0;
+
+    for validator in config.validators.iter() {
+        let delegation = utils::query_delegation(deps, &env, &validator.address)?;
+        let (delegated, rewards, can_redelegate) = match delegation {
+            Some(d) => {
+                let rewards = d.accumulated_rewards
+                    .iter()
+                    .find(|c| c.denom == ORAI)
+                    .map(|c| c.amount.u128())
+                    .unwrap_or(0);
+                (d.amount.amount.u128(), rewards, d.can_redelegate.amount.u128())
+            }
+            None => (0, 0, 0),
+        };
+
+        total_delegated = total_delegated.checked_add(delegated).unwrap();
+        total_rewards = total_rewards.checked_add(rewards).unwrap();
+        total_can_redelegate = total_can_redelegate.checked_add(can_redelegate).unwrap();
+
+        delegations.push(ValidatorDelegation {
+            validator: validator.address.clone(),
+            delegated: Uint128::new(delegated),
+            rewards: Uint128::new(rewards),
+            can_redelegate: Uint128::new(can_redelegate),
+        });
+    }
+
+    let answer = QueryResponse::Delegations {
+        delegations,
+        total_delegated: Uint128::new(total_delegated),
+        total_rewards: Uint128::new(total_rewards),
+        total_can_redelegate: Uint128::new(total_can_redelegate),
+    };
+
+    Ok(answer)
+}
+
+pub struct StakedAmount {
+    staked_usd_amount: u128,
+    staked_orai_amount: u128,
+}
+
+pub fn get_staked_amount(deps: Deps, address: &str) -> StakedAmount {
+    let delegation_query = (StakingQuery::AllDelegations {
+        delegator: address.into(),
+    }).into();
+
+    // Since we don't own 'deps', we can still use reference to execute queries
+    let all_delegations: AllDelegationsResponse = deps.querier.query(&delegation_query).unwrap();
+
+    if all_delegations.delegations.is_empty() {
+        return StakedAmount {
+            staked_orai_amount: 0,
+            staked_usd_amount: 0,
+        };
+    }
+
+    let mut staked_amount_orai = Uint128::new(0);
+
+    for delegation in all_delegations.delegations {
+        staked_amount_orai += delegation.amount.amount;
+    }
+
+    let orai_price_oracle = OraiPriceOracle::deps_new(&deps).unwrap();
+
+    let statked_usd_amount = orai_price_oracle.usd_amount(staked_amount_orai.into());
+
+    StakedAmount {
+        staked_usd_amount: statked_usd_amount,
+        staked_orai_amount: staked_amount_orai.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::StakePool;
+    use cosmwasm_std::testing::{
+        mock_dependencies,
+        mock_env,
+        mock_info,
+        MockApi,
+        MockQuerier,
+        MockStorage,
+    };
+    use crate::band::ExchangeRateResponse;
+    use cosmwasm_std::{
+        coin,
+        ContractResult,
+        Decimal,
+        FullDelegation,
+        OwnedDeps,
+        SubMsgResponse,
+        SystemError,
+        SystemResult,
+        Validator,
+        WasmQuery,
+    };
+    use serde::Serialize;
+
+    // USDT per ORAI, in the router's 6 decimals
+    const PRICE: u128 = 10_000_000;
+
+    fn chain_validators() -> [Validator; 2] {
+        ["val1", "val2"].map(|address| Validator {
+            address: address.to_string(),
+            commission: Decimal::percent(5),
+            max_commission: Decimal::percent(10),
+            max_change_rate: Decimal::percent(1),
+        })
+    }
+
+    fn setup() -> OwnedDeps<MockStorage, MockApi, MockQuerier> {
+        let mut deps = mock_dependencies();
+        deps.querier.update_staking(ORAI, &chain_validators(), &[]);
+        deps.querier.update_wasm(|query| {
+            match query {
+                WasmQuery::Smart { contract_addr, .. } if contract_addr == "router" => {
+                    let rate = ExchangeRateResponse { amount: PRICE };
+                    SystemResult::Ok(ContractResult::Ok(to_json_binary(&rate).unwrap()))
+                }
+                _ => SystemResult::Err(SystemError::Unknown {}),
+            }
+        });
+
+        let msg = InstantiateMsg {
+            admin: Some("admin".to_string()),
+            validators: vec![validator("val1", 6000), validator("val2", 4000)],
+            deposits: vec![Uint128::new(25000), Uint128::new(250)],
+            oraiswap_contract: OraiswapContract {
+                orai_swap_router_contract: "router".to_string(),
+                usdt_contract: "usdt".to_string(),
+            },
+            swap_denoms: None,
+            unbonding_period: None,
+            epoch_period: None,
+            reward_distribution: None,
+            timelock_delay: None,
+        };
+        instantiate(deps.as_mut(), mock_env(), mock_info("admin", &[]), msg).unwrap();
+
+        deps
+    }
+
+    // Config as stored before pause flags and basis-point weights
+    #[derive(Serialize)]
+    struct BaselineConfig {
+        admin: String,
+        validators: Vec<ValidatorWithWeight>,
+        status: u8,
+        usd_deposits: Vec<u128>,
+        oraiswap_contract: OraiswapContract,
+    }
+
+    fn validator(address: &str, weight: u128) -> ValidatorWithWeight {
+        ValidatorWithWeight {
+            address: address.to_string(),
+            weight,
+        }
+    }
+
+    // Makes the chain hold what the contract tracks, as if all staking went through
+    fn mirror_delegations(deps: &mut OwnedDeps<MockStorage, MockApi, MockQuerier>) {
+        let contract = mock_env().contract.address;
+        let delegations = VALIDATOR_DELEGATIONS.range(&deps.storage, None, None, Order::Ascending)
+            .map(|item| item.unwrap())
+            .filter(|(_, amount)| *amount != 0)
+            .map(|(validator, amount)| FullDelegation {
+                delegator: contract.clone(),
+                validator,
+                amount: coin(amount, ORAI),
+                can_redelegate: coin(amount, ORAI),
+                accumulated_rewards: vec![],
+            })
+            .collect::<Vec<_>>();
+        deps.querier.update_staking(ORAI, &chain_validators(), &delegations);
+    }
+
+    fn run(
+        deps: &mut OwnedDeps<MockStorage, MockApi, MockQuerier>,
+        env: &Env,
+        sender: &str,
+        funds: &[Coin],
+        msg: ExecuteMsg
+    ) -> Result<Response, ContractError> {
+        let res = execute(deps.as_mut(), env.clone(), mock_info(sender, funds), msg);
+        mirror_delegations(deps);
+        res
+    }
+
+    fn deposit_usdt(
+        deps: &mut OwnedDeps<MockStorage, MockApi, MockQuerier>,
+        env: &Env,
+        sender: &str,
+        amount: u128
+    ) {
+        let msg = ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: sender.to_string(),
+            amount: Uint128::new(amount),
+            msg: to_json_binary(&Cw20HookMsg::Deposit {}).unwrap(),
+        });
+        run(deps, env, "usdt", &[], msg).unwrap();
+    }
+
+    fn deposit_orai(
+        deps: &mut OwnedDeps<MockStorage, MockApi, MockQuerier>,
+        env: &Env,
+        sender: &str,
+        amount: u128
+    ) {
+        let msg = ExecuteMsg::Deposit { minimum_receive: None };
+        run(deps, env, sender, &coins(amount, ORAI), msg).unwrap();
+    }
+
+    #[test]
+    fn migrate_keeps_stopped_contract_paused() {
+        let mut deps = mock_dependencies();
+        let baseline = BaselineConfig {
+            admin: "admin".to_string(),
+            validators: vec![validator("val1", 60), validator("val2", 40)],
+            status: 1,
+            usd_deposits: vec![25000, 7500, 1500, 250],
+            oraiswap_contract: OraiswapContract {
+                orai_swap_router_contract: "router".to_string(),
+                usdt_contract: "usdt".to_string(),
+            },
+        };
+        deps.storage.set(b"config", &cosmwasm_std::to_json_vec(&baseline).unwrap());
+
+        migrate(deps.as_mut(), mock_env(), MigrateMsg {}).unwrap();
+
+        let config = CONFIG_ITEM.load(&deps.storage).unwrap();
+        assert_eq!(config.pause, PauseFlags::all());
+        assert_eq!(config.validators, vec![validator("val1", 6000), validator("val2", 4000)]);
+        assert_eq!(LEGACY_CONFIG_STATUS.load(&deps.storage).unwrap().status, None);
+    }
+
+    #[test]
+    fn reserve_buffered_waits_for_refills() {
+        let mut deps = mock_dependencies();
+        LIQUIDITY_BUFFER.save(&mut deps.storage, &30).unwrap();
+        BUFFER_REFILLS.save(&mut deps.storage, 2_000, &50).unwrap();
+        BUFFER_REFILLS.save(&mut deps.storage, 3_000, &40).unwrap();
+        let mut epoch = Epoch {
+            refill_amount: 100,
+            ..Default::default()
+        };
+
+        let reserved = reserve_buffered(&mut deps.storage, 1_000, 150, Some(&mut epoch)).unwrap();
+
+        assert_eq!(reserved, (90, 30));
+        assert_eq!(LIQUIDITY_BUFFER.load(&deps.storage).unwrap(), 0);
+        assert!(BUFFER_REFILLS.is_empty(&deps.storage));
+        assert_eq!(epoch.refill_amount, 70);
+
+        let err = reserve_buffered(&mut deps.storage, 1_000, 71, Some(&mut epoch)).unwrap_err();
+        assert_eq!(
+            err,
+            StdError::generic_err("Buffered deposit is not covered by the liquidity buffer")
+        );
+    }
+
+    #[test]
+    fn loss_is_shared_with_queued_withdrawals() {
+        let mut deps = setup();
+        let current_time = mock_env().block.time.seconds();
+
+        STAKE_POOL.save(&mut deps.storage, &(StakePool { shares: 600, orai: 600 })).unwrap();
+        let mut epoch = Epoch {
+            start_time: current_time,
+            refill_amount: 100,
+            ..Default::default()
+        };
+        let epoch_shares = epoch.queue_undelegation(300);
+        EPOCHS.save(&mut deps.storage, 0, &epoch).unwrap();
+        let withdrawal = UserWithdrawal {
+            amount: 350,
+            claim_time: current_time,
+            timestamp: current_time,
+            unbonding_period: 0,
+            token: DepositToken::Orai,
+            epoch: Some(0),
+            undelegated: 300,
+            refilled: 0,
+            epoch_shares,
+        };
+
+        book_loss(&mut deps.storage, current_time, 100).unwrap();
+
+        let pool = STAKE_POOL.load(&deps.storage).unwrap();
+        let epoch = EPOCHS.load(&deps.storage, 0).unwrap();
+        assert_eq!(pool.orai, 530);
+        assert_eq!(epoch.undelegate_amount, 270);
+        assert_eq!(epoch.refill_amount, 100);
+        assert_eq!(withdrawal.payout(&deps.storage).unwrap(), 320);
+
+        // Later withdrawals join the epoch at the slashed rate
+        let mut epoch = epoch;
+        let shares = epoch.queue_undelegation(90);
+        assert_eq!(epoch.undelegated_for(shares), 90);
+        assert_eq!(epoch.undelegated_for(epoch_shares), 270);
+
+        let err = book_loss(&mut deps.storage, current_time, 1_000).unwrap_err();
+        assert_eq!(err, StdError::generic_err("Slashing loss exceeds the tracked stake"));
+    }
+
+    #[test]
+    fn refill_loss_is_charged_to_the_pool() {
+        let mut deps = setup();
+        let current_time = mock_env().block.time.seconds();
+
+        // A retried undelegation carrying 100 of refill, the buffer drained
+        STAKE_POOL.save(&mut deps.storage, &(StakePool { shares: 300, orai: 300 })).unwrap();
+        let failed_epoch = Epoch {
+            processed: true,
+            refill_amount: 100,
+            ..Default::default()
+        };
+        EPOCHS.save(&mut deps.storage, 0, &failed_epoch).unwrap();
+        let current_epoch = Epoch {
+            start_time: current_time,
+            ..Default::default()
+        };
+        EPOCHS.save(&mut deps.storage, 1, &current_epoch).unwrap();
+        CURRENT_EPOCH.save(&mut deps.storage, &1).unwrap();
+        FAILED_UNDELEGATIONS.save(&mut deps.storage, 0, &100).unwrap();
+        BUFFER_REFILLS.save(&mut deps.storage, current_time, &100).unwrap();
+
+        book_loss(&mut deps.storage, current_time, 40).unwrap();
+        assert_eq!(STAKE_POOL.load(&deps.storage).unwrap().orai, 260);
+        assert_eq!(FAILED_UNDELEGATIONS.load(&deps.storage, 0).unwrap(), 100);
+        assert_eq!(BUFFER_REFILLS.load(&deps.storage, current_time).unwrap(), 100);
+
+        // With nobody staked the refill takes the loss, without failing the sync
+        STAKE_POOL.save(&mut deps.storage, &StakePool::default()).unwrap();
+        BUFFER_REFILLS.remove(&mut deps.storage, current_time);
+        book_loss(&mut deps.storage, current_time, 10).unwrap();
+        assert_eq!(FAILED_UNDELEGATIONS.load(&deps.storage, 0).unwrap(), 90);
+        assert_eq!(LIQUIDITY_BUFFER.may_load(&deps.storage).unwrap(), Some(0));
+    }
+
+    #[test]
+    fn instantiate_records_rewards_paid_to_contract() {
+        let deps = setup();
+        assert!(REWARDS_TO_CONTRACT.load(&deps.storage).unwrap());
+    }
+
+    #[test]
+    fn staking_rewards_are_recorded_from_events() {
+        let mut deps = mock_dependencies();
+        let events = vec![
+            Event::new("withdraw_rewards")
+                .add_attribute("amount", "120orai,5ibc/usdt")
+                .add_attribute("validator", "val1"),
+            Event::new("withdraw_rewards").add_attribute("amount", ""),
+            Event::new("delegate").add_attribute("amount", "1000orai")
+        ];
+
+        // Nothing is recorded while rewards are paid to another address
+        record_auto_withdrawn_rewards(&mut deps.storage, &events).unwrap();
+        assert_eq!(AUTO_WITHDRAWN_REWARDS.may_load(&deps.storage).unwrap(), None);
+
+        REWARDS_TO_CONTRACT.save(&mut deps.storage, &true).unwrap();
+        record_auto_withdrawn_rewards(&mut deps.storage, &events).unwrap();
+        record_auto_withdrawn_rewards(&mut deps.storage, &events).unwrap();
+        assert_eq!(AUTO_WITHDRAWN_REWARDS.load(&deps.storage).unwrap(), 240);
+    }
+
+    #[test]
+    fn migrate_leaves_active_contract_unpaused() {
+        let mut deps = mock_dependencies();
+        let baseline = BaselineConfig {
+            admin: "admin".to_string(),
+            validators: vec![validator("val1", 100)],
+            status: 0,
+            usd_deposits: vec![250],
+            oraiswap_contract: OraiswapContract {
+                orai_swap_router_contract: "router".to_string(),
+                usdt_contract: "usdt".to_string(),
+            },
+        };
+        deps.storage.set(b"config", &cosmwasm_std::to_json_vec(&baseline).unwrap());
+
+        let res = migrate(deps.as_mut(), mock_env(), MigrateMsg {}).unwrap();
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Distribution(DistributionMsg::SetWithdrawAddress {
+                address: mock_env().contract.address.to_string(),
+            })
+        );
+        assert!(REWARDS_TO_CONTRACT.load(&deps.storage).unwrap());
+
+        let config = CONFIG_ITEM.load(&deps.storage).unwrap();
+        assert_eq!(config.pause, PauseFlags::default());
+        assert_eq!(config.validators, vec![validator("val1", 10000)]);
+    }
+
+    #[test]
+    fn epoch_period_fits_unbonding_entries() {
+        let deps = setup();
+        let change = |epoch_period| ConfigChange::EpochPeriod { epoch_period };
+
+        assert!(validate_change(deps.as_ref(), &change(0)).is_err());
+        assert!(validate_change(deps.as_ref(), &change(UNBOUND_TIME / 7)).is_err());
+        assert!(validate_change(deps.as_ref(), &change(UNBOUND_TIME / 6 - 1)).is_err());
+        validate_change(deps.as_ref(), &change(UNBOUND_TIME / 6)).unwrap();
+        validate_change(deps.as_ref(), &change(UNBOUND_TIME)).unwrap();
+    }
+
+    #[test]
+    fn solvency_lists_tracked_and_actual_delegations() {
+        let mut deps = setup();
+        let env = mock_env();
+        let delegations = [("val1", 600), ("val2", 380)].map(|(address, amount)| FullDelegation {
+            delegator: env.contract.address.clone(),
+            validator: address.to_string(),
+            amount: coin(amount, ORAI),
+            can_redelegate: coin(amount, ORAI),
+            accumulated_rewards: vec![],
+        });
+        deps.querier.update_staking(ORAI, &chain_validators(), &delegations);
+
+        VALIDATOR_DELEGATIONS.save(&mut deps.storage, "val1".to_string(), &600).unwrap();
+        VALIDATOR_DELEGATIONS.save(&mut deps.storage, "val2".to_string(), &400).unwrap();
+        VALIDATOR_DELEGATIONS.save(&mut deps.storage, "val3".to_string(), &50).unwrap();
+
+        let QueryResponse::Solvency { delegated, validators, .. } = query_solvency(
+            deps.as_ref(),
+            env
+        ).unwrap() else {
+            panic!("unexpected response");
+        };
+
+        assert_eq!(delegated, Uint128::new(980));
+        let amounts = validators
+            .iter()
+            .map(|v| (v.validator.as_str(), v.tracked.u128(), v.actual.u128()))
+            .collect::<Vec<_>>();
+        assert_eq!(amounts, vec![("val1", 600, 600), ("val2", 400, 380), ("val3", 50, 0)]);
+    }
+
+    #[test]
+    fn split_by_weight_hands_out_the_exact_amount() {
+        let deps = setup();
+        let mut config = CONFIG_ITEM.load(&deps.storage).unwrap();
+        config.validators = vec![
+            validator("val1", 3333),
+            validator("val2", 3333),
+            validator("val3", 3334)
+        ];
+
+        let split = config.split_by_weight(1000);
+        assert_eq!(
+            split,
+            vec![("val1".to_string(), 333), ("val2".to_string(), 333), ("val3".to_string(), 334)]
+        );
+
+        // Fewer tokens than validators all land on the largest weight
+        let split = config.split_by_weight(2);
+        assert_eq!(
+            split,
+            vec![("val1".to_string(), 0), ("val2".to_string(), 0), ("val3".to_string(), 2)]
+        );
+    }
+
+    #[test]
+    fn split_by_weight_breaks_ties_on_the_first_validator() {
+        let deps = setup();
+        let mut config = CONFIG_ITEM.load(&deps.storage).unwrap();
+        config.validators = vec![validator("val1", 5000), validator("val2", 5000)];
+
+        let split = config.split_by_weight(3);
+        assert_eq!(split, vec![("val1".to_string(), 2), ("val2".to_string(), 1)]);
+    }
+
+    #[test]
+    fn split_by_weight_skips_inactive_validators() {
+        let deps = setup();
+        let mut config = CONFIG_ITEM.load(&deps.storage).unwrap();
+        config.inactive_validators = vec!["val1".to_string()];
+
+        assert_eq!(config.split_by_weight(999), vec![("val2".to_string(), 999)]);
+
+        config.inactive_validators.push("val2".to_string());
+        assert!(config.split_by_weight(999).is_empty());
+    }
+
+    fn undelegation(validator: &str, amount: u128) -> Undelegation {
+        Undelegation {
+            validator: validator.to_string(),
+            amount,
+            creation_height: None,
+        }
+    }
+
+    #[test]
+    fn take_pro_rata_takes_the_exact_amount() {
+        let mut entries = vec![
+            undelegation("val1", 100),
+            undelegation("val2", 50),
+            undelegation("val3", 50)
+        ];
+
+        let taken = take_pro_rata(&mut entries, 101);
+        assert_eq!(
+            taken,
+            vec![undelegation("val1", 51), undelegation("val2", 25), undelegation("val3", 25)]
+        );
+        assert_eq!(
+            entries,
+            vec![undelegation("val1", 49), undelegation("val2", 25), undelegation("val3", 25)]
+        );
+
+        // More than the entries hold takes everything
+        let taken = take_pro_rata(&mut entries, 1000);
+        assert_eq!(taken.iter().map(|u| u.amount).sum::<u128>(), 99);
+        assert!(entries.iter().all(|e| e.amount == 0));
+    }
+
+    #[test]
+    fn take_pro_rata_breaks_ties_on_the_first_entry() {
+        let mut entries = vec![undelegation("val1", 10), undelegation("val2", 10)];
+
+        let taken = take_pro_rata(&mut entries, 3);
+        assert_eq!(taken, vec![undelegation("val1", 2), undelegation("val2", 1)]);
+    }
+
+    #[test]
+    fn take_pro_rata_below_entry_count_skips_empty_takes() {
+        let mut entries = vec![
+            undelegation("val1", 5),
+            undelegation("val2", 5),
+            undelegation("val3", 5)
+        ];
+
+        let taken = take_pro_rata(&mut entries, 2);
+        assert_eq!(taken, vec![undelegation("val1", 2)]);
+        assert_eq!(
+            entries,
+            vec![undelegation("val1", 3), undelegation("val2", 5), undelegation("val3", 5)]
+        );
+    }
+
+    #[test]
+    fn stake_pool_prices_shares_after_a_loss() {
+        let mut pool = StakePool::default();
+        assert_eq!(pool.stake(1000), 1000);
+        assert_eq!(pool.stake(500), 500);
+
+        // A 10% slash lowers every share alike
+        pool.orai -= 150;
+        assert_eq!(pool.orai_for(1000), 900);
+        assert_eq!(pool.orai_for(500), 450);
+
+        // Joining after the loss does not take part of it
+        assert_eq!(pool.shares_for(900), 1000);
+        assert_eq!(pool.stake(900), 1000);
+        assert_eq!(pool.orai_for(1000), 900);
+
+        assert_eq!(pool.unstake(1000), 900);
+        assert_eq!(pool.unstake(500), 450);
+        assert_eq!(pool.unstake(1000), 900);
+        assert_eq!(pool, StakePool::default());
+    }
+
+    #[test]
+    fn last_staker_withdraws_the_rounding_dust() {
+        let mut pool = StakePool { shares: 3, orai: 10 };
+        assert_eq!(pool.unstake(1), 3);
+        assert_eq!(pool.unstake(2), 7);
+        assert_eq!(pool, StakePool::default());
+    }
+
+    #[test]
+    fn compound_without_shares_keeps_orai_liquid() {
+        let mut deps = setup();
+        let config = CONFIG_ITEM.load(&deps.storage).unwrap();
+
+        let messages = compound_msgs(&mut deps.storage, &config, 100).unwrap();
+        assert!(messages.is_empty());
+        assert_eq!(STAKE_POOL.may_load(&deps.storage).unwrap(), None);
+
+        STAKE_POOL.save(&mut deps.storage, &(StakePool { shares: 100, orai: 100 })).unwrap();
+        let messages = compound_msgs(&mut deps.storage, &config, 100).unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(STAKE_POOL.load(&deps.storage).unwrap(), StakePool { shares: 100, orai: 200 });
+    }
+
+    #[test]
+    fn usdt_token_is_kept_while_usdt_is_held() {
+        let mut deps = setup();
+        let change = |usdt_contract: &str| ConfigChange::Oracle {
+            oraiswap_contract: OraiswapContract {
+                orai_swap_router_contract: "router2".to_string(),
+                usdt_contract: usdt_contract.to_string(),
+            },
+        };
+        let user_info = state::UserInfo {
+            usdt_deposit: 1_000_000,
+            ..Default::default()
+        };
+        USER_INFOS.save(&mut deps.storage, "user".to_string(), &user_info).unwrap();
+
+        validate_change(deps.as_ref(), &change("usdt")).unwrap();
+        let err = validate_change(deps.as_ref(), &change("usdt2")).unwrap_err();
+        assert!(err.to_string().ends_with("USDT token cannot change while USDT is held for users"));
+
+        USER_INFOS.remove(&mut deps.storage, "user".to_string());
+        validate_change(deps.as_ref(), &change("usdt2")).unwrap();
+    }
+
+    #[test]
+    fn sudo_keeps_usdt_token_while_withdrawals_are_held() {
+        let mut deps = setup();
+        let current_time = mock_env().block.time.seconds();
+        let withdrawal = UserWithdrawal {
+            amount: 1_000_000,
+            claim_time: current_time,
+            timestamp: current_time,
+            unbonding_period: 0,
+            token: DepositToken::Usdt,
+            epoch: None,
+            undelegated: 0,
+            refilled: 0,
+            epoch_shares: 0,
+        };
+        state::withdrawals().save(&mut deps.storage, ("user".to_string(), 0), &withdrawal).unwrap();
+
+        let msg = SudoMsg::UpdateOracle {
+            oraiswap_contract: OraiswapContract {
+                orai_swap_router_contract: "router".to_string(),
+                usdt_contract: "usdt2".to_string(),
+            },
+        };
+        assert!(sudo(deps.as_mut(), mock_env(), msg).is_err());
+        let config = CONFIG_ITEM.load(&deps.storage).unwrap();
+        assert_eq!(config.oraiswap_contract.usdt_contract, "usdt");
+    }
+
+    fn staking_reply(
+        deps: &mut OwnedDeps<MockStorage, MockApi, MockQuerier>,
+        env: Env,
+        msg: &SubMsg,
+        result: Result<(), &str>
+    ) {
+        let result = match result {
+            Ok(()) => SubMsgResult::Ok(SubMsgResponse { events: vec![], data: None }),
+            Err(error) => SubMsgResult::Err(error.to_string()),
+        };
+        reply(deps.as_mut(), env, Reply { id: msg.id, result }).unwrap();
+    }
+
+    #[test]
+    fn failed_redelegation_is_unbonded_and_delegated_again() {
+        let mut deps = setup();
+        let mut env = mock_env();
+        let mut config = CONFIG_ITEM.load(&deps.storage).unwrap();
+        config.inactive_validators = vec!["val1".to_string()];
+        CONFIG_ITEM.save(&mut deps.storage, &config).unwrap();
+        VALIDATOR_DELEGATIONS.save(&mut deps.storage, "val1".to_string(), &600).unwrap();
+        VALIDATOR_DELEGATIONS.save(&mut deps.storage, "val2".to_string(), &400).unwrap();
+
+        // The redelegation is refused, which must not revert the call
+        let (messages, amount) = redelegate_msgs(&mut deps.storage, &config, "val1").unwrap();
+        assert_eq!(amount, 600);
+        assert_eq!(messages.len(), 1);
+        staking_reply(&mut deps, env.clone(), &messages[0], Err("redelegation in progress"));
+        assert_eq!(VALIDATOR_DELEGATIONS.load(&deps.storage, "val1".to_string()).unwrap(), 600);
+        assert_eq!(VALIDATOR_DELEGATIONS.load(&deps.storage, "val2".to_string()).unwrap(), 400);
+        assert_eq!(FAILED_REDELEGATIONS.load(&deps.storage, "val1".to_string()).unwrap(), 600);
+
+        // Retrying unbonds the stake instead
+        let res = try_retry_staking(deps.as_mut(), env.clone()).unwrap();
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Staking(StakingMsg::Undelegate {
+                validator: "val1".to_string(),
+                amount: coin(600, ORAI),
+            })
+        );
+        staking_reply(&mut deps, env.clone(), &res.messages[0], Ok(()));
+        assert_eq!(VALIDATOR_DELEGATIONS.load(&deps.storage, "val1".to_string()).unwrap(), 0);
+        assert!(FAILED_REDELEGATIONS.is_empty(&deps.storage));
+
+        // and delegates it to the active validators once it is liquid
+        env.block.time = env.block.time.plus_seconds(config.unbonding_period);
+        let res = try_retry_staking(deps.as_mut(), env).unwrap();
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Staking(StakingMsg::Delegate {
+                validator: "val2".to_string(),
+                amount: coin(600, ORAI),
+            })
+        );
+        assert_eq!(VALIDATOR_DELEGATIONS.load(&deps.storage, "val2".to_string()).unwrap(), 1000);
+        assert!(UNBONDING_REDELEGATIONS.is_empty(&deps.storage));
+    }
+
+    #[test]
+    fn solvency_counts_epochs_pushed_back_by_retries() {
+        let mut deps = setup();
+        let env = mock_env();
+        let current_time = env.block.time.seconds();
+        let epoch = |claim_time: u64, amount: u128| Epoch {
+            processed: true,
+            claim_time,
+            undelegations: vec![Undelegation {
+                validator: "val1".to_string(),
+                amount,
+                creation_height: None,
+            }],
+            ..Default::default()
+        };
+
+        // Epoch 0 was retried after epoch 1 had already matured
+        EPOCHS.save(&mut deps.storage, 0, &epoch(current_time + 100, 70)).unwrap();
+        EPOCHS.save(&mut deps.storage, 1, &epoch(current_time - 100, 30)).unwrap();
+        EPOCHS.save(&mut deps.storage, 2, &epoch(current_time + 200, 50)).unwrap();
+
+        let QueryResponse::Solvency { unbonding, .. } = query_solvency(
+            deps.as_ref(),
+            env
+        ).unwrap() else {
+            panic!("unexpected response");
+        };
+        assert_eq!(unbonding, Uint128::new(120));
+    }
+
+    #[test]
+    fn cancel_withdrawal_restores_usdt_deposit() {
+        let mut deps = setup();
+        let env = mock_env();
+
+        // 250 USD in USDT and the rest of the top tier in ORAI
+        deposit_usdt(&mut deps, &env, "user", 250_000_000);
+        deposit_orai(&mut deps, &env, "user", 2_500_000_000);
+        assert_eq!(USER_INFOS.load(&deps.storage, "user".to_string()).unwrap().tier, 1);
+
+        run(&mut deps, &env, "user", &[], ExecuteMsg::Withdraw {}).unwrap();
+        let withdrawals = state::withdrawals()
+            .prefix("user".to_string())
+            .range(&deps.storage, None, None, Order::Ascending)
+            .collect::<StdResult<Vec<_>>>()
+            .unwrap();
+        assert_eq!(withdrawals.len(), 2);
+        assert_eq!(withdrawals[1].1.token, DepositToken::Usdt);
+
+        let msg = ExecuteMsg::CancelWithdrawal { id: withdrawals[0].0 };
+        run(&mut deps, &env, "user", &[], msg).unwrap();
+
+        let user_info = USER_INFOS.load(&deps.storage, "user".to_string()).unwrap();
+        assert_eq!(user_info.usdt_deposit, 250_000_000);
+        assert_eq!(user_info.orai_deposit, 2_475_000_001);
+        assert_eq!(user_info.tier, 1);
+        assert!(state::withdrawals().is_empty(&deps.storage));
+    }
+
+    fn bank_sends(res: &Response) -> Vec<(String, u128)> {
+        res.messages
+            .iter()
+            .filter_map(|m| {
+                match &m.msg {
+                    CosmosMsg::Bank(BankMsg::Send { to_address, amount }) => {
+                        Some((to_address.clone(), amount[0].amount.u128()))
+                    }
+                    _ => None,
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn instant_withdraw_drains_buffer_and_epoch_refills_it() {
+        let mut deps = setup();
+        let mut env = mock_env();
+        let mut config = CONFIG_ITEM.load(&deps.storage).unwrap();
+        config.buffer_bps = 5000;
+        config.instant_withdraw_fee_bps = 100;
+        CONFIG_ITEM.save(&mut deps.storage, &config).unwrap();
+
+        // Each deposit is 25_000_001 ORAI, half of it kept in the buffer
+        for user in ["alice", "bob", "carol"] {
+            deposit_orai(&mut deps, &env, user, 30_000_000);
+        }
+        assert_eq!(LIQUIDITY_BUFFER.load(&deps.storage).unwrap(), 37_500_000);
+        assert_eq!(STAKE_POOL.load(&deps.storage).unwrap(), StakePool {
+            shares: 37_500_003,
+            orai: 37_500_003,
+        });
+
+        // Alice is paid from the buffer, her stake unbonds with the epoch to
+        // refill it and the penalty is staked for Bob and Carol
+        let res = run(&mut deps, &env, "alice", &[], ExecuteMsg::InstantWithdraw {}).unwrap();
+        assert_eq!(bank_sends(&res), vec![("alice".to_string(), 24_750_001)]);
+        assert_eq!(LIQUIDITY_BUFFER.load(&deps.storage).unwrap(), 12_499_999);
+        assert_eq!(EPOCHS.load(&deps.storage, 0).unwrap().refill_amount, 12_500_001);
+        assert_eq!(STAKE_POOL.load(&deps.storage).unwrap(), StakePool {
+            shares: 25_000_002,
+            orai: 25_250_002,
+        });
+
+        // Bob's buffered part takes the rest of the buffer, then the epoch's refill
+        run(&mut deps, &env, "bob", &[], ExecuteMsg::Withdraw {}).unwrap();
+        assert_eq!(LIQUIDITY_BUFFER.load(&deps.storage).unwrap(), 0);
+        let epoch = EPOCHS.load(&deps.storage, 0).unwrap();
+        assert_eq!(epoch.refill_amount, 12_500_000);
+        assert_eq!(epoch.undelegate_amount, 12_625_002);
+
+        env.block.time = env.block.time.plus_seconds(config.epoch_period);
+        run(&mut deps, &env, "keeper", &[], ExecuteMsg::ProcessEpoch {}).unwrap();
+        let epoch = EPOCHS.load(&deps.storage, 0).unwrap();
+        let undelegated: u128 = epoch.undelegations
+            .iter()
+            .map(|u| u.amount)
+            .sum();
+        assert_eq!(undelegated, 25_125_002);
+        assert_eq!(BUFFER_REFILLS.load(&deps.storage, epoch.claim_time).unwrap(), 12_500_000);
+
+        // Bob gets his buffered and staked parts back
+        env.block.time = env.block.time.plus_seconds(config.unbonding_period);
+        let msg = ExecuteMsg::Claim { recipient: None, limit: None };
+        let res = run(&mut deps, &env, "bob", &[], msg).unwrap();
+        assert_eq!(bank_sends(&res), vec![("bob".to_string(), 25_125_001)]);
+
+        // The matured refill covers Carol's buffered part
+        run(&mut deps, &env, "carol", &[], ExecuteMsg::Withdraw {}).unwrap();
+        assert_eq!(LIQUIDITY_BUFFER.load(&deps.storage).unwrap(), 0);
+        assert!(BUFFER_REFILLS.is_empty(&deps.storage));
+        let key = ("carol".to_string(), 1);
+        let withdrawal = state::withdrawals().load(&deps.storage, key).unwrap();
+        assert_eq!(withdrawal.amount, 25_125_001);
+        assert_eq!(withdrawal.undelegated, 12_625_001);
+        assert_eq!(STAKE_POOL.load(&deps.storage).unwrap(), StakePool::default());
+    }
+
+    #[test]
+    fn shutdown_converts_deposits_claimable_after_unbonding() {
+        let mut deps = setup();
+        let mut env = mock_env();
+        let mut config = CONFIG_ITEM.load(&deps.storage).unwrap();
+        config.buffer_bps = 2000;
+        CONFIG_ITEM.save(&mut deps.storage, &config).unwrap();
+
+        for user in ["alice", "bob", "carol"] {
+            deposit_orai(&mut deps, &env, user, 30_000_000);
+        }
+        run(&mut deps, &env, "alice", &[], ExecuteMsg::Withdraw {}).unwrap();
+
+        let msg = ExecuteMsg::EmergencyShutdown {};
+        assert!(run(&mut deps, &env, "bob", &[], msg.clone()).is_err());
+        run(&mut deps, &env, "admin", &[], msg).unwrap();
+
+        // Everything delegated unbonds at once, Alice's withdrawal included
+        let epoch = EPOCHS.load(&deps.storage, 0).unwrap();
+        assert!(epoch.processed);
+        assert_eq!(epoch.undelegate_amount, 60_000_003);
+        let undelegated: u128 = epoch.undelegations
+            .iter()
+            .map(|u| u.amount)
+            .sum();
+        assert_eq!(undelegated, 60_000_003);
+        assert_eq!(VALIDATOR_DELEGATIONS.load(&deps.storage, "val1".to_string()).unwrap(), 0);
+        assert_eq!(VALIDATOR_DELEGATIONS.load(&deps.storage, "val2".to_string()).unwrap(), 0);
+
+        let msg = ExecuteMsg::Deposit { minimum_receive: None };
+        assert!(run(&mut deps, &env, "dave", &coins(30_000_000, ORAI), msg).is_err());
+        assert!(run(&mut deps, &env, "bob", &[], ExecuteMsg::InstantWithdraw {}).is_err());
+
+        // One deposit per call, until none is left
+        let msg = ExecuteMsg::ProcessShutdown { limit: Some(1) };
+        run(&mut deps, &env, "keeper", &[], msg.clone()).unwrap();
+        let users = USER_INFOS.keys(&deps.storage, None, None, Order::Ascending)
+            .collect::<StdResult<Vec<_>>>()
+            .unwrap();
+        assert_eq!(users, vec!["carol".to_string()]);
+        run(&mut deps, &env, "keeper", &[], msg.clone()).unwrap();
+        assert!(run(&mut deps, &env, "keeper", &[], msg).is_err());
+        assert_eq!(STAKE_POOL.load(&deps.storage).unwrap(), StakePool::default());
+
+        // Converted deposits mature with the shutdown epoch
+        let claim = ExecuteMsg::Claim { recipient: None, limit: None };
+        env.block.time = env.block.time.plus_seconds(config.unbonding_period);
+        for user in ["bob", "carol"] {
+            let res = run(&mut deps, &env, user, &[], claim.clone()).unwrap();
+            assert_eq!(bank_sends(&res), vec![(user.to_string(), 25_000_001)]);
+        }
+
+        // Alice keeps the claim time her withdrawal was booked with
+        assert!(run(&mut deps, &env, "alice", &[], claim.clone()).is_err());
+        env.block.time = env.block.time.plus_seconds(config.epoch_period);
+        let res = run(&mut deps, &env, "alice", &[], claim).unwrap();
+        assert_eq!(bank_sends(&res), vec![("alice".to_string(), 25_000_001)]);
+        assert_eq!(LIQUIDITY_BUFFER.load(&deps.storage).unwrap(), 0);
+    }
+
+    fn delegations(deps: &OwnedDeps<MockStorage, MockApi, MockQuerier>) -> Vec<(String, u128)> {
+        VALIDATOR_DELEGATIONS.range(&deps.storage, None, None, Order::Ascending)
+            .collect::<StdResult<Vec<_>>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn withdrawal_unbonds_with_its_epoch_and_is_claimed_at_claim_time() {
+        let mut deps = setup();
+        let mut env = mock_env();
+        let config = CONFIG_ITEM.load(&deps.storage).unwrap();
+
+        for user in ["alice", "bob"] {
+            deposit_orai(&mut deps, &env, user, 30_000_000);
+        }
+        run(&mut deps, &env, "alice", &[], ExecuteMsg::Withdraw {}).unwrap();
+
+        let epoch = EPOCHS.load(&deps.storage, 0).unwrap();
+        assert_eq!(epoch.undelegate_amount, 25_000_001);
+        assert!(!epoch.processed);
+        assert_eq!(STAKE_POOL.load(&deps.storage).unwrap(), StakePool {
+            shares: 25_000_001,
+            orai: 25_000_001,
+        });
+
+        // The epoch only closes once its period is over
+        let msg = ExecuteMsg::ProcessEpoch {};
+        assert!(run(&mut deps, &env, "keeper", &[], msg.clone()).is_err());
+        env.block.time = env.block.time.plus_seconds(config.epoch_period);
+        run(&mut deps, &env, "keeper", &[], msg).unwrap();
+
+        let epoch = EPOCHS.load(&deps.storage, 0).unwrap();
+        assert!(epoch.processed);
+        let claim_time = env.block.time.seconds() + config.unbonding_period;
+        assert_eq!(epoch.claim_time, claim_time);
+        let undelegations = epoch.undelegations
+            .iter()
+            .map(|u| (u.validator.as_str(), u.amount))
+            .collect::<Vec<_>>();
+        assert_eq!(undelegations, vec![("val1", 15_000_001), ("val2", 10_000_000)]);
+        assert_eq!(
+            delegations(&deps),
+            vec![("val1".to_string(), 15_000_001), ("val2".to_string(), 10_000_000)]
+        );
+        assert_eq!(CURRENT_EPOCH.load(&deps.storage).unwrap(), 1);
+
+        let claim = ExecuteMsg::Claim { recipient: None, limit: None };
+        env.block.time = env.block.time.plus_seconds(config.unbonding_period - 1);
+        assert!(run(&mut deps, &env, "alice", &[], claim.clone()).is_err());
+        env.block.time = env.block.time.plus_seconds(1);
+        let res = run(&mut deps, &env, "alice", &[], claim.clone()).unwrap();
+        assert_eq!(bank_sends(&res), vec![("alice".to_string(), 25_000_001)]);
+        assert!(run(&mut deps, &env, "alice", &[], claim).is_err());
+    }
+
+    #[test]
+    fn cancel_withdrawal_before_and_after_processing() {
+        let mut deps = setup();
+        let mut env = mock_env();
+        let config = CONFIG_ITEM.load(&deps.storage).unwrap();
+        let staked = vec![("val1".to_string(), 30_000_002), ("val2".to_string(), 20_000_000)];
+        let pool = StakePool {
+            shares: 50_000_002,
+            orai: 50_000_002,
+        };
+
+        for user in ["alice", "bob"] {
+            deposit_orai(&mut deps, &env, user, 30_000_000);
+        }
+
+        // Before processing the withdrawal only leaves the epoch queue
+        run(&mut deps, &env, "alice", &[], ExecuteMsg::Withdraw {}).unwrap();
+        let msg = ExecuteMsg::CancelWithdrawal { id: 0 };
+        let res = run(&mut deps, &env, "alice", &[], msg).unwrap();
+        assert!(res.messages.is_empty());
+        assert_eq!(EPOCHS.load(&deps.storage, 0).unwrap().undelegate_amount, 0);
+        assert_eq!(STAKE_POOL.load(&deps.storage).unwrap(), pool);
+        assert_eq!(delegations(&deps), staked);
+        let user_info = USER_INFOS.load(&deps.storage, "alice".to_string()).unwrap();
+        assert_eq!((user_info.orai_deposit, user_info.shares), (25_000_001, 25_000_001));
+
+        // After processing its unbonding entries are cancelled
+        run(&mut deps, &env, "alice", &[], ExecuteMsg::Withdraw {}).unwrap();
+        env.block.time = env.block.time.plus_seconds(config.epoch_period);
+        run(&mut deps, &env, "keeper", &[], ExecuteMsg::ProcessEpoch {}).unwrap();
+        let msg = ExecuteMsg::CancelWithdrawal { id: 1 };
+        let res = run(&mut deps, &env, "alice", &[], msg).unwrap();
+        assert_eq!(res.messages.len(), 2);
+        assert!(res.messages.iter().all(|m| m.id == STAKING_REWARDS_REPLY_ID));
+        let epoch = EPOCHS.load(&deps.storage, 0).unwrap();
+        assert_eq!(epoch.undelegate_amount, 0);
+        assert!(epoch.undelegations.iter().all(|u| u.amount == 0));
+        assert_eq!(STAKE_POOL.load(&deps.storage).unwrap(), pool);
+        assert_eq!(delegations(&deps), staked);
+
+        // Once unbonded the withdrawal can only be claimed
+        run(&mut deps, &env, "alice", &[], ExecuteMsg::Withdraw {}).unwrap();
+        env.block.time = env.block.time.plus_seconds(config.epoch_period);
+        run(&mut deps, &env, "keeper", &[], ExecuteMsg::ProcessEpoch {}).unwrap();
+        env.block.time = env.block.time.plus_seconds(config.unbonding_period);
+        let msg = ExecuteMsg::CancelWithdrawal { id: 2 };
+        let err = run(&mut deps, &env, "alice", &[], msg).unwrap_err();
+        assert!(err.to_string().ends_with("Withdrawal is not unbonding"));
+        assert_eq!(EPOCHS.load(&deps.storage, 1).unwrap().undelegate_amount, 25_000_001);
+    }
+
+    #[test]
+    fn admin_proposal_must_be_accepted_in_time() {
+        let mut deps = setup();
+        let mut env = mock_env();
+
+        let msg = ExecuteMsg::ProposeAdmin {
+            admin: "new_admin".to_string(),
+            expires_in: Some(100),
+        };
+        assert!(run(&mut deps, &env, "new_admin", &[], msg.clone()).is_err());
+        run(&mut deps, &env, "admin", &[], msg).unwrap();
+
+        assert!(run(&mut deps, &env, "other", &[], ExecuteMsg::AcceptAdmin {}).is_err());
+
+        env.block.time = env.block.time.plus_seconds(100);
+        let err = run(&mut deps, &env, "new_admin", &[], ExecuteMsg::AcceptAdmin {}).unwrap_err();
+        assert!(matches!(err, ContractError::AdminProposalExpired { .. }));
+        assert_eq!(CONFIG_ITEM.load(&deps.storage).unwrap().admin, "admin");
+
+        // A fresh proposal can still be accepted before it expires
+        let msg = ExecuteMsg::ProposeAdmin {
+            admin: "new_admin".to_string(),
+            expires_in: None,
+        };
+        run(&mut deps, &env, "admin", &[], msg).unwrap();
+        run(&mut deps, &env, "new_admin", &[], ExecuteMsg::AcceptAdmin {}).unwrap();
+        assert_eq!(CONFIG_ITEM.load(&deps.storage).unwrap().admin, "new_admin");
+        assert!(PENDING_ADMIN.may_load(&deps.storage).unwrap().is_none());
+    }
+
+    #[test]
+    fn only_the_owner_grants_roles() {
+        let mut deps = setup();
+        let env = mock_env();
+
+        let grant = |address: &str| ExecuteMsg::GrantRole {
+            role: Role::Pauser,
+            address: address.to_string(),
+        };
+        let err = run(&mut deps, &env, "mallory", &[], grant("mallory")).unwrap_err();
+        assert!(err.to_string().ends_with("Unauthorized"));
+
+        run(&mut deps, &env, "admin", &[], grant("pauser")).unwrap();
+        // A role holder cannot hand out roles either
+        let err = run(&mut deps, &env, "pauser", &[], grant("mallory")).unwrap_err();
+        assert!(err.to_string().ends_with("Unauthorized"));
+        assert!(!ROLE_MEMBERS.has(&deps.storage, ("pauser".to_string(), "mallory".to_string())));
+
+        let flags = PauseFlags {
+            deposits: true,
+            ..PauseFlags::default()
+        };
+        assert!(run(&mut deps, &env, "mallory", &[], ExecuteMsg::Pause { flags }).is_err());
+        run(&mut deps, &env, "pauser", &[], ExecuteMsg::Pause { flags }).unwrap();
+        assert!(CONFIG_ITEM.load(&deps.storage).unwrap().pause.deposits);
+
+        let revoke = ExecuteMsg::RevokeRole {
+            role: Role::Pauser,
+            address: "pauser".to_string(),
+        };
+        assert!(run(&mut deps, &env, "pauser", &[], revoke.clone()).is_err());
+        run(&mut deps, &env, "admin", &[], revoke).unwrap();
+        assert!(run(&mut deps, &env, "pauser", &[], ExecuteMsg::Pause { flags }).is_err());
+    }
+
+    #[test]
+    fn changes_need_the_role_and_the_timelock() {
+        let mut deps = setup();
+        let mut env = mock_env();
+
+        let grant = ExecuteMsg::GrantRole {
+            role: Role::Pauser,
+            address: "pauser".to_string(),
+        };
+        run(&mut deps, &env, "admin", &[], grant).unwrap();
+
+        let propose = ExecuteMsg::ProposeChange {
+            change: ConfigChange::Validators {
+                validators: vec![validator("val1", 10000)],
+            },
+        };
+        let err = run(&mut deps, &env, "pauser", &[], propose.clone()).unwrap_err();
+        assert!(err.to_string().ends_with("Unauthorized"));
+        assert!(PENDING_CHANGES.is_empty(&deps.storage));
+
+        run(&mut deps, &env, "admin", &[], propose).unwrap();
+        let executable_at = PENDING_CHANGES.load(&deps.storage, 0).unwrap().executable_at;
+        assert_eq!(executable_at, env.block.time.seconds() + TIMELOCK_DELAY);
+
+        env.block.time = env.block.time.plus_seconds(TIMELOCK_DELAY - 1);
+        let err = run(&mut deps, &env, "admin", &[], ExecuteMsg::ExecuteChange { id: 0 })
+            .unwrap_err();
+        assert!(err.to_string().ends_with(&format!("executed after {}", executable_at)));
+        let config = CONFIG_ITEM.load(&deps.storage).unwrap();
+        assert_eq!(config.validators, vec![validator("val1", 6000), validator("val2", 4000)]);
+
+        env.block.time = env.block.time.plus_seconds(1);
+        run(&mut deps, &env, "admin", &[], ExecuteMsg::ExecuteChange { id: 0 }).unwrap();
+        let config = CONFIG_ITEM.load(&deps.storage).unwrap();
+        assert_eq!(config.validators, vec![validator("val1", 10000)]);
+        assert!(PENDING_CHANGES.is_empty(&deps.storage));
+    }
+
+    #[test]
+    fn sudo_set_admin_drops_pending_handover() {
+        let mut deps = setup();
+        let env = mock_env();
+
+        let msg = ExecuteMsg::ProposeAdmin {
+            admin: "new_admin".to_string(),
+            expires_in: None,
+        };
+        run(&mut deps, &env, "admin", &[], msg).unwrap();
+        assert!(PENDING_ADMIN.may_load(&deps.storage).unwrap().is_some());
+
+        let msg = SudoMsg::SetAdmin {
+            admin: "governance".to_string(),
+        };
+        sudo(deps.as_mut(), env.clone(), msg).unwrap();
+        assert_eq!(CONFIG_ITEM.load(&deps.storage).unwrap().admin, "governance");
+        assert!(PENDING_ADMIN.may_load(&deps.storage).unwrap().is_none());
+
+        let err = run(&mut deps, &env, "new_admin", &[], ExecuteMsg::AcceptAdmin {}).unwrap_err();
+        assert!(matches!(err, ContractError::NoAdminProposal {}));
+        assert_eq!(CONFIG_ITEM.load(&deps.storage).unwrap().admin, "governance");
+    }
+
+    fn staking_msg_to(res: &Response, validator: &str) -> SubMsg {
+        res.messages
+            .iter()
+            .find(|m| {
+                match &m.msg {
+                    CosmosMsg::Staking(StakingMsg::Delegate { validator: v, .. }) |
+                    CosmosMsg::Staking(StakingMsg::Undelegate { validator: v, .. }) => {
+                        v == validator
+                    }
+                    _ => false,
+                }
+            })
+            .unwrap()
+            .clone()
+    }
+
+    #[test]
+    fn failed_delegation_is_delegated_again_on_retry() {
+        let mut deps = setup();
+        let env = mock_env();
+
+        let msg = ExecuteMsg::Deposit { minimum_receive: None };
+        let res = run(&mut deps, &env, "alice", &coins(30_000_000, ORAI), msg).unwrap();
+        let failed = staking_msg_to(&res, "val2");
+        staking_reply(&mut deps, env.clone(), &failed, Err("validator jailed"));
+        mirror_delegations(&mut deps);
+
+        assert_eq!(PENDING_DELEGATION.load(&deps.storage).unwrap(), 10_000_000);
+        assert_eq!(
+            delegations(&deps),
+            vec![("val1".to_string(), 15_000_001), ("val2".to_string(), 0)]
+        );
+        // The failed amount still belongs to the pool
+        assert_eq!(STAKE_POOL.load(&deps.storage).unwrap().orai, 25_000_001);
+
+        let res = run(&mut deps, &env, "keeper", &[], ExecuteMsg::RetryStaking {}).unwrap();
+        assert_eq!(res.messages.len(), 2);
+        assert_eq!(PENDING_DELEGATION.load(&deps.storage).unwrap(), 0);
+        assert_eq!(
+            delegations(&deps),
+            vec![("val1".to_string(), 21_000_001), ("val2".to_string(), 4_000_000)]
+        );
+        assert!(run(&mut deps, &env, "keeper", &[], ExecuteMsg::RetryStaking {}).is_err());
+    }
+
+    #[test]
+    fn failed_undelegation_is_undelegated_again_on_retry() {
+        let mut deps = setup();
+        let mut env = mock_env();
+        let config = CONFIG_ITEM.load(&deps.storage).unwrap();
+
+        for user in ["alice", "bob"] {
+            deposit_orai(&mut deps, &env, user, 30_000_000);
+        }
+        run(&mut deps, &env, "alice", &[], ExecuteMsg::Withdraw {}).unwrap();
+        env.block.time = env.block.time.plus_seconds(config.epoch_period);
+        let res = run(&mut deps, &env, "keeper", &[], ExecuteMsg::ProcessEpoch {}).unwrap();
+
+        let failed = staking_msg_to(&res, "val2");
+        staking_reply(&mut deps, env.clone(), &failed, Err("too many unbonding entries"));
+        mirror_delegations(&mut deps);
+
+        assert_eq!(FAILED_UNDELEGATIONS.load(&deps.storage, 0).unwrap(), 10_000_000);
+        let epoch = EPOCHS.load(&deps.storage, 0).unwrap();
+        let undelegations = epoch.undelegations
+            .iter()
+            .map(|u| (u.validator.as_str(), u.amount))
+            .collect::<Vec<_>>();
+        assert_eq!(undelegations, vec![("val1", 15_000_001)]);
+        assert_eq!(
+            delegations(&deps),
+            vec![("val1".to_string(), 15_000_001), ("val2".to_string(), 20_000_000)]
+        );
+
+        // The retried part pushes the claim time back by its own unbonding
+        env.block.time = env.block.time.plus_seconds(60);
+        run(&mut deps, &env, "keeper", &[], ExecuteMsg::RetryStaking {}).unwrap();
+        assert!(FAILED_UNDELEGATIONS.is_empty(&deps.storage));
+        let epoch = EPOCHS.load(&deps.storage, 0).unwrap();
+        let undelegated = epoch.undelegations.iter().map(|u| u.amount).sum::<u128>();
+        assert_eq!(undelegated, 25_000_001);
+        assert_eq!(epoch.claim_time, env.block.time.seconds() + config.unbonding_period);
+        let delegated = delegations(&deps).iter().map(|(_, amount)| amount).sum::<u128>();
+        assert_eq!(delegated, 25_000_001);
+    }
+
+    fn reward_shares() -> Vec<RewardShare> {
+        [("treasury", 3333), ("dev", 3334), ("ops", 3333)]
+            .map(|(address, share_bps)| RewardShare {
+                address: address.to_string(),
+                share_bps,
+            })
+            .to_vec()
+    }
+
+    fn rewards_reply(deps: &mut OwnedDeps<MockStorage, MockApi, MockQuerier>, id: u64) -> Response {
+        let result = SubMsgResult::Ok(SubMsgResponse { events: vec![], data: None });
+        reply(deps.as_mut(), mock_env(), Reply { id, result }).unwrap()
+    }
+
+    #[test]
+    fn withdrawn_rewards_follow_the_distribution() {
+        let mut deps = setup();
+        let env = mock_env();
+        let mut config = CONFIG_ITEM.load(&deps.storage).unwrap();
+        config.reward_distribution = reward_shares();
+        CONFIG_ITEM.save(&mut deps.storage, &config).unwrap();
+        AUTO_WITHDRAWN_REWARDS.save(&mut deps.storage, &2).unwrap();
+
+        let msg = ExecuteMsg::WithdrawRewards {
+            recipient: None,
+            swap_minimum_price: None,
+        };
+        let res = run(&mut deps, &env, "admin", &[], msg).unwrap();
+        assert_eq!(res.messages.len(), 1);
+        assert_eq!(res.messages[0].id, WITHDRAW_REWARDS_REPLY_ID);
+
+        // Rewards landed on the contract alongside those swept by staking messages
+        deps.querier.update_balance(env.contract.address.clone(), coins(1000, ORAI));
+        let res = rewards_reply(&mut deps, WITHDRAW_REWARDS_REPLY_ID);
+        assert_eq!(
+            bank_sends(&res),
+            vec![("treasury".to_string(), 333), ("dev".to_string(), 336), ("ops".to_string(), 333)]
+        );
+        assert!(AUTO_WITHDRAWN_REWARDS.may_load(&deps.storage).unwrap().is_none());
+        assert!(PENDING_REWARDS.may_load(&deps.storage).unwrap().is_none());
+    }
+
+    fn mock_usdt_balance(deps: &mut OwnedDeps<MockStorage, MockApi, MockQuerier>, balance: u128) {
+        deps.querier.update_wasm(move |query| {
+            match query {
+                WasmQuery::Smart { contract_addr, .. } if contract_addr == "usdt" => {
+                    let response = cw20::BalanceResponse { balance: Uint128::new(balance) };
+                    SystemResult::Ok(ContractResult::Ok(to_json_binary(&response).unwrap()))
+                }
+                _ => SystemResult::Err(SystemError::Unknown {}),
+            }
+        });
+    }
+
+    fn usdt_transfers(res: &Response) -> Vec<(String, u128)> {
+        res.messages
+            .iter()
+            .filter_map(|m| {
+                match &m.msg {
+                    CosmosMsg::Wasm(WasmMsg::Execute { contract_addr, msg, .. }) if
+                        contract_addr == "usdt"
+                    => {
+                        match from_json(msg).unwrap() {
+                            cw20::Cw20ExecuteMsg::Transfer { recipient, amount } => {
+                                Some((recipient, amount.u128()))
+                            }
+                            _ => None,
+                        }
+                    }
+                    _ => None,
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn swapped_rewards_are_paid_pro_rata() {
+        let mut deps = setup();
+        let env = mock_env();
+        let mut config = CONFIG_ITEM.load(&deps.storage).unwrap();
+        config.reward_distribution = reward_shares();
+        CONFIG_ITEM.save(&mut deps.storage, &config).unwrap();
+        AUTO_WITHDRAWN_REWARDS.save(&mut deps.storage, &1000).unwrap();
+
+        let msg = ExecuteMsg::WithdrawRewards {
+            recipient: None,
+            swap_minimum_price: Some(Decimal::percent(10)),
+        };
+        run(&mut deps, &env, "admin", &[], msg).unwrap();
+
+        // USDT already on the contract belongs to depositors, not to the swap
+        mock_usdt_balance(&mut deps, 50);
+        let res = rewards_reply(&mut deps, WITHDRAW_REWARDS_REPLY_ID);
+        assert_eq!(res.messages.len(), 1);
+        assert_eq!(res.messages[0].id, SWAP_REWARDS_REPLY_ID);
+        match &res.messages[0].msg {
+            CosmosMsg::Wasm(WasmMsg::Execute { contract_addr, funds, .. }) => {
+                assert_eq!(contract_addr, "router");
+                assert_eq!(funds, &coins(1000, ORAI));
+            }
+            msg => panic!("unexpected message {:?}", msg),
+        }
+        let pending = PENDING_REWARD_SWAP.load(&deps.storage).unwrap();
+        assert_eq!(pending.usdt_balance, 50);
+        assert_eq!(
+            pending.payouts,
+            vec![("treasury".to_string(), 333), ("dev".to_string(), 334), ("ops".to_string(), 333)]
+        );
+
+        mock_usdt_balance(&mut deps, 50 + 101);
+        let res = rewards_reply(&mut deps, SWAP_REWARDS_REPLY_ID);
+        assert_eq!(
+            usdt_transfers(&res),
+            vec![("treasury".to_string(), 33), ("dev".to_string(), 35), ("ops".to_string(), 33)]
+        );
+        assert!(PENDING_REWARD_SWAP.may_load(&deps.storage).unwrap().is_none());
+    }
 }
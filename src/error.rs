@@ -6,6 +6,38 @@ pub enum ContractError {
     #[error("{0}")] Std(#[from] StdError),
 
     #[error("Unauthorized")] Unauthorized {},
+
+    #[error("No admin proposal")] NoAdminProposal {},
+
+    #[error("Admin proposal expired at {expires_at}")] AdminProposalExpired {
+        expires_at: u64,
+    },
+
+    #[error("Validators list is empty")] NoValidators {},
+
+    #[error("Validator weights add up to {total} instead of 10000 bps")] InvalidValidatorWeights {
+        total: u128,
+    },
+
+    #[error("Validator {validator} has zero weight")] ZeroValidatorWeight {
+        validator: String,
+    },
+
+    #[error("Validator {validator} is listed more than once")] DuplicateValidator {
+        validator: String,
+    },
+
+    #[error("Validator {validator} does not exist")] UnknownValidator {
+        validator: String,
+    },
+
+    #[error("Reward shares add up to {total} instead of 10000 bps")] InvalidRewardShares {
+        total: u64,
+    },
+
+    #[error("Reward recipient {address} is listed more than once")] DuplicateRewardRecipient {
+        address: String,
+    },
     // Add any other custom errors you like here.
     // Look at https://docs.rs/thiserror/1.0.21/thiserror/ for details.
 }
@@ -1,4 +1,5 @@
-use cosmwasm_std::Uint128;
+use cosmwasm_std::{ Decimal, Uint128 };
+use cw20::Cw20ReceiveMsg;
 use schemars::JsonSchema;
 use serde::{ Deserialize, Serialize };
 
@@ -9,26 +10,85 @@ pub enum ResponseStatus {
     Failure,
 }
 
-#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, JsonSchema, Debug)]
+/// Operations that can be paused independently of each other
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, JsonSchema, Debug)]
+#[serde(rename_all = "snake_case")]
+pub struct PauseFlags {
+    #[serde(default)]
+    pub deposits: bool,
+    #[serde(default)]
+    pub withdrawals: bool,
+    #[serde(default)]
+    pub claims: bool,
+    #[serde(default)]
+    pub rewards: bool,
+}
+
+impl PauseFlags {
+    pub fn all() -> Self {
+        PauseFlags {
+            deposits: true,
+            withdrawals: true,
+            claims: true,
+            rewards: true,
+        }
+    }
+
+    /// Flags paused in either `self` or `other`
+    pub fn union(&self, other: &PauseFlags) -> Self {
+        PauseFlags {
+            deposits: self.deposits || other.deposits,
+            withdrawals: self.withdrawals || other.withdrawals,
+            claims: self.claims || other.claims,
+            rewards: self.rewards || other.rewards,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, JsonSchema, Debug)]
 #[serde(rename_all = "snake_case")]
-#[repr(u8)]
-pub enum ContractStatus {
-    Active,
-    Stopped,
+pub enum Role {
+    Owner,
+    Pauser,
+    ConfigManager,
+    RewardManager,
+    Keeper,
 }
 
-impl From<u8> for ContractStatus {
-    fn from(status: u8) -> Self {
-        if status == (ContractStatus::Active as u8) {
-            ContractStatus::Active
-        } else if status == (ContractStatus::Stopped as u8) {
-            ContractStatus::Stopped
-        } else {
-            panic!("Wrong status");
+impl Role {
+    pub const GRANTABLE: [Role; 4] = [
+        Role::Pauser,
+        Role::ConfigManager,
+        Role::RewardManager,
+        Role::Keeper,
+    ];
+
+    pub fn key(&self) -> &'static str {
+        match self {
+            Role::Owner => "owner",
+            Role::Pauser => "pauser",
+            Role::ConfigManager => "config_manager",
+            Role::RewardManager => "reward_manager",
+            Role::Keeper => "keeper",
         }
     }
 }
 
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq, Eq, JsonSchema, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum DepositToken {
+    #[default]
+    Orai,
+    Usdt,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, JsonSchema, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum WithdrawalStatus {
+    Pending,
+    Matured,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub struct InstantiateMsg {
@@ -36,27 +96,153 @@ pub struct InstantiateMsg {
     pub validators: Vec<ValidatorWithWeight>,
     pub deposits: Vec<Uint128>,
     pub oraiswap_contract: OraiswapContract,
+    pub swap_denoms: Option<Vec<String>>,
+    pub unbonding_period: Option<u64>,
+    pub epoch_period: Option<u64>,
+    pub reward_distribution: Option<Vec<RewardShare>>,
+    pub timelock_delay: Option<u64>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct MigrateMsg {}
+
+/// Chain governance overrides, usable without the admin key
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SudoMsg {
+    SetAdmin {
+        admin: String,
+    },
+    SetPauseFlags {
+        flags: PauseFlags,
+    },
+    UpdateOracle {
+        oraiswap_contract: OraiswapContract,
+    },
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum ExecuteMsg {
-    ChangeAdmin {
+    ProposeAdmin {
         admin: String,
+        // Seconds the proposal can be accepted for, a week by default
+        expires_in: Option<u64>,
     },
-    ChangeStatus {
-        status: ContractStatus,
+    AcceptAdmin {},
+    CancelAdminProposal {},
+    RenounceAdmin {},
+    GrantRole {
+        role: Role,
+        address: String,
+    },
+    RevokeRole {
+        role: Role,
+        address: String,
+    },
+    // Pausing is instant, unpausing goes through `ProposeChange`
+    Pause {
+        flags: PauseFlags,
+    },
+    ProposeChange {
+        change: ConfigChange,
+    },
+    ExecuteChange {
+        id: u64,
+    },
+    CancelChange {
+        id: u64,
+    },
+    SyncUnbondingPeriod {},
+    Deposit {
+        minimum_receive: Option<Uint128>,
     },
-    Deposit {},
     Withdraw {},
+    InstantWithdraw {},
+    CancelWithdrawal {
+        id: u64,
+    },
     Claim {
         recipient: Option<String>,
-        start: Option<u32>,
+        limit: Option<u32>,
+    },
+    ClaimFor {
+        addresses: Vec<String>,
         limit: Option<u32>,
     },
     WithdrawRewards {
         recipient: Option<String>,
+        // Swaps the rewards to USDT, receiving at least this much USDT per ORAI
+        swap_minimum_price: Option<Decimal>,
+    },
+    ProcessEpoch {},
+    // Unbonds all stake and stops deposits for good
+    EmergencyShutdown {},
+    // Turns deposits left after a shutdown into withdrawals, `limit` at a time
+    ProcessShutdown {
+        limit: Option<u32>,
+    },
+    CheckValidators {},
+    RetryStaking {},
+    Compound {},
+    Receive(Cw20ReceiveMsg),
+}
+
+/// Config change queued behind the timelock
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ConfigChange {
+    PauseFlags {
+        flags: PauseFlags,
+    },
+    Deposits {
+        deposits: Vec<Uint128>,
+    },
+    Validators {
+        validators: Vec<ValidatorWithWeight>,
+    },
+    Oracle {
+        oraiswap_contract: OraiswapContract,
     },
+    SwapDenoms {
+        denoms: Vec<String>,
+    },
+    EpochPeriod {
+        epoch_period: u64,
+    },
+    LiquidityBuffer {
+        buffer_bps: u64,
+        instant_withdraw_fee_bps: u64,
+        penalty_recipient: Option<String>,
+    },
+    AutoCompound {
+        enabled: bool,
+    },
+    RewardDistribution {
+        distribution: Vec<RewardShare>,
+    },
+    TimelockDelay {
+        delay: u64,
+    },
+}
+
+impl ConfigChange {
+    /// Role allowed to propose and cancel the change
+    pub fn role(&self) -> Role {
+        match self {
+            ConfigChange::PauseFlags { .. } => Role::Pauser,
+            ConfigChange::RewardDistribution { .. } => Role::RewardManager,
+            ConfigChange::TimelockDelay { .. } => Role::Owner,
+            _ => Role::ConfigManager,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Cw20HookMsg {
+    Deposit {},
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
@@ -65,7 +251,7 @@ pub enum ExecuteResponse {
     ChangeAdmin {
         status: ResponseStatus,
     },
-    ChangeStatus {
+    Pause {
         status: ResponseStatus,
     },
     Deposit {
@@ -77,18 +263,61 @@ pub enum ExecuteResponse {
     Withdraw {
         status: ResponseStatus,
     },
+    CancelWithdrawal {
+        usd_deposit: Uint128,
+        orai_deposit: Uint128,
+        tier: u8,
+        status: ResponseStatus,
+    },
+    InstantWithdraw {
+        amount: Uint128,
+        penalty: Uint128,
+        usdt_amount: Uint128,
+        status: ResponseStatus,
+    },
     Claim {
         amount: Uint128,
+        usdt_amount: Uint128,
+        status: ResponseStatus,
+    },
+    ClaimFor {
+        users: u32,
+        amount: Uint128,
+        usdt_amount: Uint128,
         status: ResponseStatus,
     },
     WithdrawRewards {
         amount: Uint128,
+        usdt_amount: Uint128,
         status: ResponseStatus,
     },
     Redelegate {
         amount: Uint128,
         status: ResponseStatus,
     },
+    ProcessEpoch {
+        epoch: u64,
+        amount: Uint128,
+        status: ResponseStatus,
+    },
+    EmergencyShutdown {
+        epoch: u64,
+        amount: Uint128,
+        status: ResponseStatus,
+    },
+    ProcessShutdown {
+        users: u64,
+        status: ResponseStatus,
+    },
+    RetryStaking {
+        delegated: Uint128,
+        undelegated: Uint128,
+        status: ResponseStatus,
+    },
+    Compound {
+        amount: Uint128,
+        status: ResponseStatus,
+    },
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
@@ -100,17 +329,81 @@ pub enum QueryMsg {
     },
     Withdrawals {
         address: String,
-        start: Option<u32>,
+        status: Option<WithdrawalStatus>,
+        start_after: Option<u64>,
         limit: Option<u32>,
     },
+    UnbondingSchedule {
+        from: u64,
+        to: u64,
+        bucket_seconds: u64,
+    },
+    CurrentEpoch {},
+    Solvency {},
+    Delegations {},
+    PendingAdmin {},
+    Roles {},
+    PendingChanges {},
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub struct SerializedWithdrawals {
+    pub id: u64,
     pub amount: Uint128,
     pub claim_time: u64,
     pub timestamp: u64,
+    pub unbonding_period: u64,
+    pub token: DepositToken,
+    pub epoch: Option<u64>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct UnbondingBucket {
+    pub start_time: u64,
+    pub amount: Uint128,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct SerializedChange {
+    pub id: u64,
+    pub change: ConfigChange,
+    pub proposer: String,
+    pub executable_at: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct RoleMembers {
+    pub role: Role,
+    pub members: Vec<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct RewardShare {
+    pub address: String,
+    pub share_bps: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct ValidatorDelegation {
+    pub validator: String,
+    pub delegated: Uint128,
+    pub rewards: Uint128,
+    pub can_redelegate: Uint128,
+}
+
+/// Delegation the contract tracks for a validator next to the one on chain
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct ValidatorSolvency {
+    pub validator: String,
+    pub tracked: Uint128,
+    pub actual: Uint128,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
@@ -119,26 +412,77 @@ pub enum QueryResponse {
     Config {
         admin: String,
         validators: Vec<ValidatorWithWeight>,
-        status: ContractStatus,
+        pause: PauseFlags,
+        shutdown: bool,
         usd_deposits: Vec<Uint128>,
         min_tier: u8,
         oraiswap_contract: OraiswapContract,
+        swap_denoms: Vec<String>,
+        unbonding_period: u64,
+        buffer_bps: u64,
+        instant_withdraw_fee_bps: u64,
+        penalty_recipient: Option<String>,
+        epoch_period: u64,
+        inactive_validators: Vec<String>,
+        auto_compound: bool,
+        reward_distribution: Vec<RewardShare>,
+        timelock_delay: u64,
     },
     UserInfo {
         tier: u8,
         timestamp: u64,
         usd_deposit: Uint128,
         orai_deposit: Uint128,
+        usdt_deposit: Uint128,
     },
     Withdrawals {
-        amount: u32,
         withdrawals: Vec<SerializedWithdrawals>,
+        total_claimable: Uint128,
+        total_usdt_claimable: Uint128,
+    },
+    UnbondingSchedule {
+        buckets: Vec<UnbondingBucket>,
+        total: Uint128,
+    },
+    Epoch {
+        id: u64,
+        start_time: u64,
+        end_time: u64,
+        undelegate_amount: Uint128,
+        refill_amount: Uint128,
+    },
+    Solvency {
+        user_deposits: Uint128,
+        pending_withdrawals: Uint128,
+        delegated: Uint128,
+        unbonding: Uint128,
+        balance: Uint128,
+        surplus: Uint128,
+        shortfall: Uint128,
+        validators: Vec<ValidatorSolvency>,
+    },
+    PendingAdmin {
+        admin: Option<String>,
+        expires_at: Option<u64>,
+    },
+    Roles {
+        roles: Vec<RoleMembers>,
+    },
+    PendingChanges {
+        changes: Vec<SerializedChange>,
+    },
+    Delegations {
+        delegations: Vec<ValidatorDelegation>,
+        total_delegated: Uint128,
+        total_rewards: Uint128,
+        total_can_redelegate: Uint128,
     },
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, JsonSchema)]
 pub struct ValidatorWithWeight {
     pub address: String,
+    // Share of the stake in basis points, all weights add up to 10000
     pub weight: u128,
 }
 
@@ -146,7 +490,7 @@ impl Clone for ValidatorWithWeight {
     fn clone(&self) -> ValidatorWithWeight {
         ValidatorWithWeight {
             address: self.address.clone(),
-            weight: self.weight.clone(), // Handle other fields accordingly.
+            weight: self.weight, // Handle other fields accordingly.
         }
     }
 }
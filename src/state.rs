@@ -1,25 +1,157 @@
+use crate::contract::{ EPOCH_PERIOD, MAX_BPS, TIMELOCK_DELAY, UNBOUND_TIME };
 use crate::msg::{
-    ContractStatus,
+    ConfigChange,
+    DepositToken,
     OraiswapContract,
+    PauseFlags,
     QueryResponse,
+    RewardShare,
+    Role,
+    SerializedChange,
     SerializedWithdrawals,
     ValidatorWithWeight,
 };
-use cosmwasm_std::{ StdError, StdResult, Storage, Uint128 };
-use cw_storage_plus::{ Item, Map };
+use cosmwasm_std::{ Decimal, StdError, StdResult, Storage, Uint128 };
+use cw_storage_plus::{ Index, IndexList, IndexedMap, Item, KeyDeserialize, Map, MultiIndex };
 use serde::{ Deserialize, Serialize };
 
 pub const CONFIG_ITEM: Item<Config> = Item::new("config");
-pub const WITHDRAWALS_LIST: Map<String, Vec<UserWithdrawal>> = Map::new("withdraw"); //Deque<UserWithdrawal> = Deque::new("withdraw");
+// Binary status stored in the config before pause flags
+pub const LEGACY_CONFIG_STATUS: Item<LegacyConfigStatus> = Item::new("config");
+pub const PENDING_ADMIN: Item<PendingAdmin> = Item::new("pending_admin");
+// Members keyed by `(role, address)`, the owner being `Config.admin`
+pub const ROLE_MEMBERS: Map<(String, String), bool> = Map::new("role_members");
+pub const CHANGE_ID: Item<u64> = Item::new("change_id");
+pub const PENDING_CHANGES: Map<u64, PendingChange> = Map::new("pending_changes");
+// Layout before withdrawals were indexed, only read by `migrate`
+pub const LEGACY_WITHDRAWALS_LIST: Map<String, Vec<UserWithdrawal>> = Map::new("withdraw");
+pub const WITHDRAWAL_ID: Item<u64> = Item::new("withdrawal_id");
 pub const USER_INFOS: Map<String, UserInfo> = Map::new("user_info");
+pub const PENDING_SWAP_DEPOSIT: Item<PendingSwapDeposit> = Item::new("pending_swap_deposit");
+pub const PENDING_REWARDS: Item<PendingRewards> = Item::new("pending_rewards");
+pub const PENDING_REWARD_SWAP: Item<PendingRewardSwap> = Item::new("pending_reward_swap");
+// Whether the SDK pays rewards to the contract, as it does from instantiation
+// until a reward withdrawal sets another recipient
+pub const REWARDS_TO_CONTRACT: Item<bool> = Item::new("rewards_to_contract");
+// Rewards paid to the contract by staking messages, swept into the next withdrawal
+pub const AUTO_WITHDRAWN_REWARDS: Item<u128> = Item::new("auto_withdrawn_rewards");
+pub const LIQUIDITY_BUFFER: Item<u128> = Item::new("liquidity_buffer");
+pub const BUFFER_REFILLS: Map<u64, u128> = Map::new("buffer_refills");
+pub const CURRENT_EPOCH: Item<u64> = Item::new("current_epoch");
+pub const EPOCHS: Map<u64, Epoch> = Map::new("epochs");
+pub const VALIDATOR_DELEGATIONS: Map<String, u128> = Map::new("validator_delegations");
+pub const STAKE_POOL: Item<StakePool> = Item::new("stake_pool");
+// Epoch that unbonded all stake in an emergency shutdown
+pub const SHUTDOWN_EPOCH: Item<u64> = Item::new("shutdown_epoch");
+pub const STAKING_OP_ID: Item<u64> = Item::new("staking_op_id");
+pub const STAKING_OPS: Map<u64, StakingOp> = Map::new("staking_ops");
+// Deposits kept liquid after a failed delegation, waiting for `RetryStaking`
+pub const PENDING_DELEGATION: Item<u128> = Item::new("pending_delegation");
+// ORAI per epoch that failed to undelegate, waiting for `RetryStaking`
+pub const FAILED_UNDELEGATIONS: Map<u64, u128> = Map::new("failed_undelegations");
+// Stake per validator that failed to redelegate, unbonded by `RetryStaking` instead
+pub const FAILED_REDELEGATIONS: Map<String, u128> = Map::new("failed_redelegations");
+// Stake unbonding by completion time, delegated again by `RetryStaking` once liquid
+pub const UNBONDING_REDELEGATIONS: Map<u64, u128> = Map::new("unbonding_redelegations");
+
+pub struct WithdrawalIndexes<'a> {
+    pub claim_time: MultiIndex<'a, u64, UserWithdrawal, (String, u64)>,
+    pub user_claim_time: MultiIndex<'a, (String, u64), UserWithdrawal, (String, u64)>,
+}
+
+impl IndexList<UserWithdrawal> for WithdrawalIndexes<'_> {
+    fn get_indexes(&'_ self) -> Box<dyn Iterator<Item = &'_ dyn Index<UserWithdrawal>> + '_> {
+        let v: Vec<&dyn Index<UserWithdrawal>> = vec![&self.claim_time, &self.user_claim_time];
+        Box::new(v.into_iter())
+    }
+}
+
+/// Withdrawals keyed by `(user, id)` and indexed by claim time, globally and per user
+pub fn withdrawals<'a>() -> IndexedMap<'a, (String, u64), UserWithdrawal, WithdrawalIndexes<'a>> {
+    let indexes = WithdrawalIndexes {
+        claim_time: MultiIndex::new(
+            |_pk, w: &UserWithdrawal| w.claim_time,
+            "withdrawals",
+            "withdrawals__claim_time"
+        ),
+        user_claim_time: MultiIndex::new(
+            |pk: &[u8], w: &UserWithdrawal| {
+                let (user, _) = <(String, u64) as KeyDeserialize>::from_slice(pk).unwrap();
+                (user, w.claim_time)
+            },
+            "withdrawals",
+            "withdrawals__user_claim_time"
+        ),
+    };
+    IndexedMap::new("withdrawals", indexes)
+}
+
+pub fn next_withdrawal_id(storage: &mut dyn Storage) -> StdResult<u64> {
+    let id = WITHDRAWAL_ID.may_load(storage)?.unwrap_or_default();
+    WITHDRAWAL_ID.save(storage, &id.checked_add(1).unwrap())?;
+    Ok(id)
+}
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub struct Config {
     pub admin: String,
     pub validators: Vec<ValidatorWithWeight>,
-    pub status: u8,
+    #[serde(default)]
+    pub pause: PauseFlags,
+    // Set by an emergency shutdown, after which no deposits are accepted
+    #[serde(default)]
+    pub shutdown: bool,
     pub usd_deposits: Vec<u128>,
     pub oraiswap_contract: OraiswapContract,
+    #[serde(default)]
+    pub swap_denoms: Vec<String>,
+    #[serde(default = "default_unbonding_period")]
+    pub unbonding_period: u64,
+    #[serde(default)]
+    pub buffer_bps: u64,
+    #[serde(default)]
+    pub instant_withdraw_fee_bps: u64,
+    #[serde(default)]
+    pub penalty_recipient: Option<String>,
+    #[serde(default = "default_epoch_period")]
+    pub epoch_period: u64,
+    // Jailed or unbonded validators, skipped when splitting stake
+    #[serde(default)]
+    pub inactive_validators: Vec<String>,
+    // Rewards are delegated back into the stake pool instead of swept to the admin
+    #[serde(default)]
+    pub auto_compound: bool,
+    // Recipients of withdrawn rewards, the contract itself standing for stakers
+    #[serde(default)]
+    pub reward_distribution: Vec<RewardShare>,
+    #[serde(default = "default_timelock_delay")]
+    pub timelock_delay: u64,
+}
+
+fn default_unbonding_period() -> u64 {
+    UNBOUND_TIME
+}
+
+fn default_epoch_period() -> u64 {
+    EPOCH_PERIOD
+}
+
+fn default_timelock_delay() -> u64 {
+    TIMELOCK_DELAY
+}
+
+fn assert_not_paused(paused: bool, operation: &str) -> StdResult<()> {
+    if paused {
+        return Err(StdError::generic_err(format!("{} are paused", operation)));
+    }
+
+    Ok(())
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct LegacyConfigStatus {
+    #[serde(default)]
+    pub status: Option<u8>,
 }
 
 impl Config {
@@ -53,30 +185,138 @@ impl Config {
             .unwrap() as u8
     }
 
-    pub fn assert_contract_active(&self) -> StdResult<()> {
-        let active = ContractStatus::Active as u8;
-        if self.status != active {
-            return Err(StdError::generic_err("Contract is not active"));
+    pub fn active_validators(&self) -> Vec<&ValidatorWithWeight> {
+        self.validators
+            .iter()
+            .filter(|v| !self.inactive_validators.contains(&v.address))
+            .collect()
+    }
+
+    /// Splits `amount` by weight between active validators, the rounding
+    /// remainder going to the validator with the largest weight
+    pub fn split_by_weight(&self, amount: u128) -> Vec<(String, u128)> {
+        let validators = self.active_validators();
+        let total_weight: u128 = validators
+            .iter()
+            .map(|v| v.weight)
+            .sum();
+        if total_weight == 0 {
+            return vec![];
+        }
+
+        let mut split: Vec<(String, u128)> = validators
+            .iter()
+            .map(|v| {
+                let individual_amount = Uint128::from(amount)
+                    .multiply_ratio(v.weight, total_weight)
+                    .u128();
+                (v.address.clone(), individual_amount)
+            })
+            .collect();
+
+        let split_amount: u128 = split
+            .iter()
+            .map(|(_, a)| a)
+            .sum();
+        let remainder = amount.checked_sub(split_amount).unwrap();
+
+        // On equal weights the first validator in the list gets the remainder
+        let largest = (0..validators.len()).rev().max_by_key(|i| validators[*i].weight);
+        if let Some(index) = largest {
+            split[index].1 = split[index].1.checked_add(remainder).unwrap();
+        }
+
+        split
+    }
+
+    /// Splits `amount` between reward recipients, the rounding remainder
+    /// going to the largest share
+    pub fn split_rewards(&self, amount: u128) -> Vec<(String, u128)> {
+        let shares = &self.reward_distribution;
+        let mut split: Vec<(String, u128)> = shares
+            .iter()
+            .map(|s| {
+                let share_amount = Uint128::from(amount)
+                    .multiply_ratio(s.share_bps, MAX_BPS)
+                    .u128();
+                (s.address.clone(), share_amount)
+            })
+            .collect();
+
+        let split_amount: u128 = split
+            .iter()
+            .map(|(_, a)| a)
+            .sum();
+        let remainder = amount.saturating_sub(split_amount);
+
+        let largest = (0..shares.len()).rev().max_by_key(|i| shares[*i].share_bps);
+        if let Some(index) = largest {
+            split[index].1 = split[index].1.checked_add(remainder).unwrap();
+        }
+
+        split
+    }
+
+    /// Passes for the owner, who holds every role, and for members of `role`
+    pub fn assert_role(&self, storage: &dyn Storage, sender: &str, role: Role) -> StdResult<()> {
+        if !self.admin.is_empty() && sender == self.admin {
+            return Ok(());
+        }
+
+        let key = (role.key().to_string(), sender.to_string());
+        if role == Role::Owner || !ROLE_MEMBERS.has(storage, key) {
+            return Err(StdError::generic_err("Unauthorized"));
         }
 
         Ok(())
     }
 
+    pub fn assert_deposits_active(&self) -> StdResult<()> {
+        if self.shutdown {
+            return Err(StdError::generic_err("Contract is shut down"));
+        }
+
+        assert_not_paused(self.pause.deposits, "Deposits")
+    }
+
+    pub fn assert_withdrawals_active(&self) -> StdResult<()> {
+        assert_not_paused(self.pause.withdrawals, "Withdrawals")
+    }
+
+    pub fn assert_claims_active(&self) -> StdResult<()> {
+        assert_not_paused(self.pause.claims, "Claims")
+    }
+
+    pub fn assert_rewards_active(&self) -> StdResult<()> {
+        assert_not_paused(self.pause.rewards, "Reward operations")
+    }
+
     pub fn to_answer(&self) -> StdResult<QueryResponse> {
         let admin = self.admin.clone(); //api.addr_humanize(&self.admin)?;
         let min_tier = self.usd_deposits.len().checked_add(1).unwrap() as u8;
 
-        return Ok(QueryResponse::Config {
+        Ok(QueryResponse::Config {
             admin,
             min_tier,
             validators: self.validators.clone(),
             oraiswap_contract: self.oraiswap_contract.clone(),
-            status: self.status.into(),
+            swap_denoms: self.swap_denoms.clone(),
+            unbonding_period: self.unbonding_period,
+            buffer_bps: self.buffer_bps,
+            instant_withdraw_fee_bps: self.instant_withdraw_fee_bps,
+            penalty_recipient: self.penalty_recipient.clone(),
+            epoch_period: self.epoch_period,
+            inactive_validators: self.inactive_validators.clone(),
+            auto_compound: self.auto_compound,
+            reward_distribution: self.reward_distribution.clone(),
+            timelock_delay: self.timelock_delay,
+            pause: self.pause,
+            shutdown: self.shutdown,
             usd_deposits: self.usd_deposits
                 .iter()
                 .map(|d| Uint128::from(*d))
                 .collect(),
-        });
+        })
     }
 }
 
@@ -86,15 +326,27 @@ pub struct UserInfo {
     pub timestamp: u64,
     pub usd_deposit: u128,
     pub orai_deposit: u128,
+    #[serde(default)]
+    pub usdt_deposit: u128,
+    // Part of "orai_deposit" kept liquid in the buffer instead of delegated
+    #[serde(default)]
+    pub orai_buffered: u128,
+    // Stake pool shares held for the delegated part of "orai_deposit"
+    #[serde(default)]
+    pub shares: u128,
 }
 
 impl UserInfo {
-    pub fn to_answer(&self) -> QueryResponse {
+    /// Answers with the deposit's current ORAI value, which grows as rewards compound
+    pub fn to_answer(&self, pool: &StakePool) -> QueryResponse {
+        let orai_deposit = self.orai_buffered.checked_add(pool.orai_for(self.shares)).unwrap();
+
         QueryResponse::UserInfo {
             tier: self.tier,
             timestamp: self.timestamp,
             usd_deposit: Uint128::from(self.usd_deposit),
-            orai_deposit: Uint128::from(self.orai_deposit),
+            orai_deposit: Uint128::from(orai_deposit),
+            usdt_deposit: Uint128::from(self.usdt_deposit),
         }
     }
 }
@@ -104,14 +356,272 @@ pub struct UserWithdrawal {
     pub amount: u128,
     pub claim_time: u64,
     pub timestamp: u64,
+    #[serde(default = "default_unbonding_period")]
+    pub unbonding_period: u64,
+    #[serde(default)]
+    pub token: DepositToken,
+    // Epoch that unbonds the withdrawal and the amount it added to the epoch
+    #[serde(default)]
+    pub epoch: Option<u64>,
+    #[serde(default)]
+    pub undelegated: u128,
+    // Buffered part reserved from refills that were still unbonding
+    #[serde(default)]
+    pub refilled: u128,
+    // Shares of the epoch's queued undelegation behind "undelegated", one per
+    // ORAI when unset
+    #[serde(default)]
+    pub epoch_shares: u128,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Undelegation {
+    pub validator: String,
+    pub amount: u128,
+    // Set for retried undelegations, which don't share the epoch's height
+    #[serde(default)]
+    pub creation_height: Option<u64>,
 }
 
 impl UserWithdrawal {
-    pub fn to_serialized(&self) -> SerializedWithdrawals {
-        SerializedWithdrawals {
-            amount: Uint128::from(self.amount),
-            claim_time: self.claim_time,
+    /// Claim time taken from the withdrawal's epoch, `None` until the epoch is processed
+    pub fn claimable_at(&self, storage: &dyn Storage) -> StdResult<Option<u64>> {
+        match self.epoch {
+            None => Ok(Some(self.claim_time)),
+            Some(id) => {
+                let epoch = EPOCHS.load(storage, id)?;
+                Ok(epoch.processed.then_some(epoch.claim_time))
+            }
+        }
+    }
+
+    pub fn epoch_shares(&self) -> u128 {
+        match self.epoch_shares {
+            0 => self.undelegated,
+            shares => shares,
+        }
+    }
+
+    /// ORAI paid on claim, the undelegated part bearing any loss booked on its epoch
+    pub fn payout(&self, storage: &dyn Storage) -> StdResult<u128> {
+        let Some(id) = self.epoch else {
+            return Ok(self.amount);
+        };
+
+        let epoch = EPOCHS.load(storage, id)?;
+        let undelegated = epoch.undelegated_for(self.epoch_shares());
+        Ok(self.amount.checked_sub(self.undelegated).unwrap().checked_add(undelegated).unwrap())
+    }
+
+    pub fn to_serialized(
+        &self,
+        storage: &dyn Storage,
+        id: u64
+    ) -> StdResult<SerializedWithdrawals> {
+        let claim_time = self.claimable_at(storage)?.unwrap_or(self.claim_time);
+
+        Ok(SerializedWithdrawals {
+            id,
+            amount: Uint128::from(self.payout(storage)?),
+            claim_time,
             timestamp: self.timestamp,
+            unbonding_period: self.unbonding_period,
+            token: self.token.clone(),
+            epoch: self.epoch,
+        })
+    }
+}
+
+/// Delegated ORAI owed to users and the shares it is split into, so that
+/// slashing losses are shared by every staker
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct StakePool {
+    pub shares: u128,
+    pub orai: u128,
+}
+
+impl StakePool {
+    pub fn orai_for(&self, shares: u128) -> u128 {
+        if self.shares == 0 {
+            return 0;
+        }
+
+        Uint128::from(shares).multiply_ratio(self.orai, self.shares).u128()
+    }
+
+    pub fn shares_for(&self, orai: u128) -> u128 {
+        if self.shares == 0 || self.orai == 0 {
+            return orai;
+        }
+
+        Uint128::from(orai).multiply_ratio(self.shares, self.orai).u128()
+    }
+
+    /// Adds delegated `orai` to the pool, returning the shares issued for it
+    pub fn stake(&mut self, orai: u128) -> u128 {
+        let shares = self.shares_for(orai);
+        self.shares = self.shares.checked_add(shares).unwrap();
+        self.orai = self.orai.checked_add(orai).unwrap();
+        shares
+    }
+
+    /// Burns `shares`, returning the ORAI they were worth
+    pub fn unstake(&mut self, shares: u128) -> u128 {
+        let orai = self.orai_for(shares);
+        self.shares = self.shares.checked_sub(shares).unwrap();
+        self.orai = self.orai.checked_sub(orai).unwrap();
+        orai
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum StakingAction {
+    Delegate,
+    Undelegate,
+    Redelegate {
+        src_validator: String,
+    },
+    // Undelegates stake that could not be redelegated, to delegate it again
+    Unbond,
+}
+
+/// Staking message in flight, looked up by reply id to undo its bookkeeping on failure
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct StakingOp {
+    pub action: StakingAction,
+    pub validator: String,
+    pub amount: u128,
+    pub epoch: Option<u64>,
+}
+
+/// Rewards being withdrawn to the contract, compounded or distributed in `reply`
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PendingRewards {
+    pub orai_balance: u128,
+    // Recipient when no reward distribution is configured
+    #[serde(default)]
+    pub recipient: Option<String>,
+    #[serde(default)]
+    pub swap_minimum_price: Option<Decimal>,
+}
+
+/// Rewards being swapped to USDT, paid out in `reply` in proportion to `payouts`
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PendingRewardSwap {
+    pub amount: u128,
+    pub usdt_balance: u128,
+    pub payouts: Vec<(String, u128)>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct PendingChange {
+    pub change: ConfigChange,
+    pub proposer: String,
+    pub executable_at: u64,
+}
+
+impl PendingChange {
+    pub fn to_serialized(&self, id: u64) -> SerializedChange {
+        SerializedChange {
+            id,
+            change: self.change.clone(),
+            proposer: self.proposer.clone(),
+            executable_at: self.executable_at,
+        }
+    }
+}
+
+/// Admin handover waiting for the proposed address to accept it
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PendingAdmin {
+    pub address: String,
+    pub expires_at: u64,
+}
+
+/// Deposit waiting for the oraiswap router to return ORAI, finished in `reply`
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PendingSwapDeposit {
+    pub sender: String,
+    pub orai_balance: u128,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Epoch {
+    pub start_time: u64,
+    // ORAI to unbond for user withdrawals and for liquidity buffer refills
+    pub undelegate_amount: u128,
+    pub refill_amount: u128,
+    // Set once the epoch total is undelegated
+    pub processed: bool,
+    pub creation_height: u64,
+    pub claim_time: u64,
+    pub undelegations: Vec<Undelegation>,
+    // Withdrawal shares of "undelegate_amount", so that a slashing loss booked
+    // while the epoch is queued is shared by its withdrawals
+    #[serde(default)]
+    pub undelegate_shares: u128,
+}
+
+impl Epoch {
+    /// Queued undelegation as a pool of withdrawal shares, one share per ORAI
+    /// for epochs queued before shares were tracked
+    fn undelegate_pool(&self) -> StakePool {
+        let shares = match self.undelegate_shares {
+            0 => self.undelegate_amount,
+            shares => shares,
+        };
+
+        StakePool {
+            shares,
+            orai: self.undelegate_amount,
+        }
+    }
+
+    fn set_undelegate_pool(&mut self, pool: StakePool) {
+        self.undelegate_shares = pool.shares;
+        self.undelegate_amount = pool.orai;
+    }
+
+    /// Queues `orai` for undelegation, returning the withdrawal shares issued for it
+    pub fn queue_undelegation(&mut self, orai: u128) -> u128 {
+        let mut pool = self.undelegate_pool();
+        let shares = pool.stake(orai);
+        self.set_undelegate_pool(pool);
+        shares
+    }
+
+    /// Removes `shares` from the queue, returning the ORAI they were worth
+    pub fn dequeue_undelegation(&mut self, shares: u128) -> u128 {
+        let mut pool = self.undelegate_pool();
+        let orai = pool.unstake(shares);
+        self.set_undelegate_pool(pool);
+        orai
+    }
+
+    pub fn undelegation_shares_for(&self, orai: u128) -> u128 {
+        self.undelegate_pool().shares_for(orai)
+    }
+
+    pub fn undelegated_for(&self, shares: u128) -> u128 {
+        self.undelegate_pool().orai_for(shares)
+    }
+
+    /// Books a slashing loss against the queued undelegation
+    pub fn book_undelegation_loss(&mut self, loss: u128) -> StdResult<()> {
+        let mut pool = self.undelegate_pool();
+        pool.orai = Uint128::new(pool.orai).checked_sub(Uint128::new(loss))?.u128();
+        self.set_undelegate_pool(pool);
+        Ok(())
+    }
+
+    pub fn to_answer(&self, id: u64, epoch_period: u64) -> QueryResponse {
+        QueryResponse::Epoch {
+            id,
+            start_time: self.start_time,
+            end_time: self.start_time.checked_add(epoch_period).unwrap(),
+            undelegate_amount: Uint128::from(self.undelegate_amount),
+            refill_amount: Uint128::from(self.refill_amount),
         }
     }
 }
@@ -1,13 +1,22 @@
 use crate::contract::ORAI;
-use cosmwasm_std::{ Addr, Coin, DepsMut, Env, FullDelegation, StdResult };
+use cosmwasm_std::{
+    to_json_binary,
+    Addr,
+    Binary,
+    Coin,
+    CosmosMsg,
+    Deps,
+    Env,
+    FullDelegation,
+    QueryRequest,
+    StdError,
+    StdResult,
+    Uint128,
+    WasmMsg,
+};
+use cw20::{ BalanceResponse as Cw20BalanceResponse, Cw20ExecuteMsg, Cw20QueryMsg };
 use serde::Deserialize;
 
-#[derive(Debug, Deserialize)]
-#[serde(rename_all = "snake_case")]
-struct FixedDelegationResponse {
-    pub _delegation: Option<FixedFullDelegation>,
-}
-
 #[derive(Debug, Deserialize)]
 pub struct FixedFullDelegation {
     pub delegator: String,
@@ -33,7 +42,7 @@ impl From<FixedFullDelegation> for FullDelegation {
 }
 
 pub fn query_delegation(
-    deps: &DepsMut,
+    deps: Deps,
     env: &Env,
     validator: &String
 ) -> StdResult<Option<FullDelegation>> {
@@ -41,3 +50,96 @@ pub fn query_delegation(
 
     Ok(delegation)
 }
+
+pub fn query_cw20_balance(deps: Deps, token: &str, address: &str) -> StdResult<u128> {
+    let response: Cw20BalanceResponse = deps.querier.query_wasm_smart(
+        token,
+        &(Cw20QueryMsg::Balance {
+            address: address.to_string(),
+        })
+    )?;
+
+    Ok(response.balance.u128())
+}
+
+pub fn cw20_transfer_msg(token: &str, recipient: &str, amount: u128) -> StdResult<CosmosMsg> {
+    let transfer_msg = Cw20ExecuteMsg::Transfer {
+        recipient: recipient.to_string(),
+        amount: Uint128::new(amount),
+    };
+
+    Ok(
+        CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: token.to_string(),
+            msg: to_json_binary(&transfer_msg)?,
+            funds: vec![],
+        })
+    )
+}
+
+#[derive(Debug, Deserialize)]
+struct StakingParamsResponse {
+    params: StakingParams,
+}
+
+#[derive(Debug, Deserialize)]
+struct StakingParams {
+    // google.protobuf.Duration in its JSON form, e.g. "1814400s"
+    unbonding_time: String,
+}
+
+pub fn query_unbonding_period(deps: Deps) -> StdResult<u64> {
+    let request = QueryRequest::Stargate {
+        path: "/cosmos.staking.v1beta1.Query/Params".to_string(),
+        data: Binary::default(),
+    };
+    let response: StakingParamsResponse = deps.querier.query(&request)?;
+
+    let unbonding_time = response.params.unbonding_time;
+    unbonding_time
+        .trim_end_matches('s')
+        .split('.')
+        .next()
+        .and_then(|seconds| seconds.parse::<u64>().ok())
+        .ok_or_else(|| {
+            StdError::generic_err(format!("Invalid unbonding time: {}", unbonding_time))
+        })
+}
+
+fn encode_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(((value as u8) & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn encode_bytes_field(buf: &mut Vec<u8>, field_number: u64, bytes: &[u8]) {
+    encode_varint(buf, (field_number << 3) | 2);
+    encode_varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+pub fn cancel_unbonding_msg(
+    delegator: &str,
+    validator: &str,
+    amount: u128,
+    creation_height: u64
+) -> CosmosMsg {
+    let mut coin = Vec::new();
+    encode_bytes_field(&mut coin, 1, ORAI.as_bytes());
+    encode_bytes_field(&mut coin, 2, amount.to_string().as_bytes());
+
+    // cosmos.staking.v1beta1.MsgCancelUnbondingDelegation
+    let mut value = Vec::new();
+    encode_bytes_field(&mut value, 1, delegator.as_bytes());
+    encode_bytes_field(&mut value, 2, validator.as_bytes());
+    encode_bytes_field(&mut value, 3, &coin);
+    encode_varint(&mut value, 4 << 3);
+    encode_varint(&mut value, creation_height);
+
+    CosmosMsg::Stargate {
+        type_url: "/cosmos.staking.v1beta1.MsgCancelUnbondingDelegation".to_string(),
+        value: Binary::from(value),
+    }
+}